@@ -6,45 +6,96 @@ extern crate failure;
 
 extern crate openqa;
 
+use std::env;
+use std::process;
+
 use futures::future;
 use hyper::rt::{self, Future};
 
 use openqa::*;
 
-fn run() -> impl Future<Item=(), Error=()> {
+const USAGE: &str = "Usage: new_job_templates GROUP DISTRI VERSION FLAVOR \
+                     ARCH:MACHINE[,ARCH:MACHINE...] TEST...";
+
+struct Args {
+    group: String,
+    distri: String,
+    version: String,
+    flavor: String,
+    prod_machines: Vec<(String, String)>,
+    tests: Vec<String>,
+}
+
+fn args() -> Option<Args> {
+    let mut args = env::args().skip(1);
+    let (group, distri, version, flavor) = (args.next()?, args.next()?, args.next()?,
+                                            args.next()?);
+    let prod_machines = args.next()?.split(',').map(|pm| {
+        let mut pm = pm.splitn(2, ':');
+        Some((pm.next()?.to_string(), pm.next()?.to_string()))
+    }).collect::<Option<Vec<_>>>()?;
+    let tests: Vec<String> = args.collect();
+
+    if tests.is_empty() {
+        return None;
+    }
+    Some(Args { group, distri, version, flavor, prod_machines, tests })
+}
+
+fn run(args: Args) -> impl Future<Item=(), Error=()> {
     let oqa = OpenQA::with_conf_file("~/.config/openqa/client.conf",
                                      "openqa.suse.de").unwrap();
 
-    let prod_machines: [(i32, i32);3] = [(385, 60), (397, 95), (399, 94)];
-    let tests: [i32;11] = [2189, 2697, 2199, 2625, 2197, 2198, 2205, 2178,
-                           2183, 2184, 2192];
-
-    for test in &tests {
-        for (prod, machine) in &prod_machines {
-            let jt = JobTemplate {
-                product_id: *prod,
-                machine_id: *machine,
-                group_id: 158,
-                test_suite_id: *test,
+    let mut templates = Vec::new();
+    for test in &args.tests {
+        for (arch, machine) in &args.prod_machines {
+            let prod = ProductKey::new(args.distri.as_str(), args.version.as_str(),
+                                       args.flavor.as_str(), arch.as_str());
+            match oqa.job_template_from_names(&args.group, &prod, machine, test).wait() {
+                Ok(jt) => templates.push((jt, test, prod, machine)),
+                Err(e) => {
+                    println!("Can't resolve job template: {}", e);
+                    return future::err(());
+                },
             };
+        }
+    }
 
-            match oqa.new_job_template(&jt).wait().unwrap() {
-                CreateResult::Ok(id) => {
-                    println!("Created new job template: id={}, test={}, product={}, machine={}",
-                             id, test, prod, machine);
-                },
-                CreateResult::Err(err) => {
-                    println!("Failed to create job template; test={}, product={}, machine={}: {}",
-                             test, prod, machine, err);
-                    return future::err(());
-                }
-            }
+    let requests = templates.iter().map(|(jt, _, _, _)| {
+        oqa.new_job_template(jt).and_then(CreateResult::into_result)
+    });
+    let report = oqa.bulk(4, OnError::Continue, requests).wait().unwrap();
+
+    for ((_, test, prod, machine), outcome) in templates.iter().zip(&report.outcomes) {
+        match outcome {
+            Outcome::Done(id) => {
+                println!("Created new job template: id={}, test={}, product={}, machine={}",
+                         id, test, prod, machine);
+            },
+            Outcome::Failed(err) => {
+                println!("Failed to create job template; test={}, product={}, machine={}: {}",
+                         test, prod, machine, err);
+            },
+            Outcome::Skipped => (),
         }
     }
+    println!("{}", report);
 
-    future::ok(())
+    if report.is_ok() {
+        future::ok(())
+    } else {
+        future::err(())
+    }
 }
 
 fn main() {
-    rt::run(rt::lazy(run));
+    let args = match args() {
+        Some(args) => args,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        },
+    };
+
+    rt::run(rt::lazy(move || run(args)));
 }
@@ -0,0 +1,228 @@
+extern crate hyper;
+extern crate futures;
+extern crate serde;
+extern crate serde_json;
+#[macro_use]
+extern crate failure;
+
+extern crate openqa;
+
+use std::env;
+use std::process;
+
+use hyper::rt::{self, Future};
+use futures::future;
+use serde::Serialize;
+use serde_json::Value;
+use failure::Error;
+
+use openqa::*;
+use openqa::lint::glob_match;
+
+const USAGE: &str = "\
+Usage: openqa-rs [OPTIONS] COMMAND [ARGS]
+
+Options:
+    --host HOST      Host section of the config to use (default: localhost)
+    --config FILE    Config file (default: ~/.config/openqa/client.conf)
+    --json           Print JSON instead of a table
+    -h, --help       Show this help
+
+Commands:
+    test-suites [list [GLOB] | delete ID]
+    machines [list [GLOB] | delete ID]
+    products [list [GLOB] | delete ID]
+    job-templates [list [GLOB] | delete ID]
+    api METHOD PATH [KEY=VALUE...] [--data JSON]
+
+GLOB matches test suite and machine names, product keys (distri-version-flavor-arch)
+and job template test suite names.";
+
+struct Opts {
+    host: String,
+    config: String,
+    json: bool,
+    args: Vec<String>,
+}
+
+fn parse_opts() -> Result<Opts, Error> {
+    let mut opts = Opts {
+        host: "localhost".to_string(),
+        config: "~/.config/openqa/client.conf".to_string(),
+        json: false,
+        args: Vec::new(),
+    };
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--host" => opts.host = args.next().ok_or_else(|| format_err!("--host needs a value"))?,
+            "--config" => {
+                opts.config = args.next().ok_or_else(|| format_err!("--config needs a value"))?
+            },
+            "--json" => opts.json = true,
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            a if a.starts_with("--") && opts.args.is_empty() => bail!("Unknown option {}", a),
+            _ => opts.args.push(arg),
+        }
+    }
+
+    if opts.args.is_empty() {
+        bail!("No command given");
+    }
+    Ok(opts)
+}
+
+/// Print rows with the columns padded to the same width
+fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: &mut dyn Iterator<Item=&str>| {
+        let cells: Vec<String> = cells.zip(&widths)
+            .map(|(c, w)| format!("{:w$}", c, w = *w))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    };
+    line(&mut header.iter().cloned());
+    for row in rows {
+        line(&mut row.iter().map(|c| c.as_str()));
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), Error> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn pattern(args: &[String]) -> &str {
+    args.get(1).map(|s| s.as_str()).unwrap_or("*")
+}
+
+fn table(oqa: &OpenQA, opts: &Opts) -> Result<(), Error> {
+    let args = &opts.args[1..];
+    let action = args.first().map(|s| s.as_str()).unwrap_or("list");
+
+    if action == "delete" {
+        let id: i32 = args.get(1).ok_or_else(|| format_err!("delete needs an ID"))?.parse()?;
+        let res = match opts.args[0].as_str() {
+            "test-suites" => oqa.del_test_suite(id).wait()?,
+            "machines" => oqa.del_machine(id).wait()?,
+            "products" => oqa.del_product(id).wait()?,
+            "job-templates" => oqa.del_job_template(id).wait()?,
+            t => bail!("Unknown command {}", t),
+        };
+        res.into_result()?;
+        println!("Deleted {}", id);
+        return Ok(());
+    } else if action != "list" {
+        bail!("Unknown action {}", action);
+    }
+
+    let glob = pattern(args);
+    match opts.args[0].as_str() {
+        "test-suites" => {
+            let mut items = oqa.get_test_suites().wait()?.test_suites;
+            items.retain(|t| glob_match(glob, &t.name));
+            if opts.json {
+                return print_json(&items);
+            }
+            print_table(&["ID", "NAME", "DESCRIPTION"], &items.iter().map(|t| {
+                vec![t.id.to_string(), t.name.clone(),
+                     t.description.lines().next().unwrap_or_default().to_string()]
+            }).collect::<Vec<_>>());
+        },
+        "machines" => {
+            let mut items = oqa.get_machines().wait()?.machines;
+            items.retain(|m| glob_match(glob, &m.name));
+            if opts.json {
+                return print_json(&items);
+            }
+            print_table(&["ID", "NAME", "BACKEND"], &items.iter().map(|m| {
+                vec![m.id.to_string(), m.name.clone(), m.backend.clone()]
+            }).collect::<Vec<_>>());
+        },
+        "products" => {
+            let mut items = oqa.get_products().wait()?.products;
+            items.retain(|p| glob_match(glob, &ProductKey::from(p).to_string()));
+            if opts.json {
+                return print_json(&items);
+            }
+            print_table(&["ID", "DISTRI", "VERSION", "FLAVOR", "ARCH"], &items.iter().map(|p| {
+                vec![p.id.to_string(), p.distri.clone(), p.version.clone(),
+                     p.flavor.clone(), p.arch.clone()]
+            }).collect::<Vec<_>>());
+        },
+        "job-templates" => {
+            let mut items = oqa.get_job_templates().wait()?.job_templates;
+            items.retain(|t| glob_match(glob, &t.test_suite.name));
+            if opts.json {
+                return print_json(&items);
+            }
+            print_table(&["ID", "GROUP", "PRODUCT", "MACHINE", "TEST SUITE", "PRIO"],
+                        &items.iter().map(|t| {
+                vec![t.id.to_string(), t.group_name.clone(),
+                     ProductKey::from(&t.product).to_string(), t.machine.name.clone(),
+                     t.test_suite.name.clone(), t.prio.to_string()]
+            }).collect::<Vec<_>>());
+        },
+        t => bail!("Unknown command {}", t),
+    }
+
+    Ok(())
+}
+
+fn api(oqa: &OpenQA, opts: &Opts) -> Result<(), Error> {
+    let mut args = opts.args[1..].iter();
+    let (method, path) = match (args.next(), args.next()) {
+        (Some(m), Some(p)) => (m.to_uppercase(), p.trim_start_matches('/')),
+        _ => bail!("api needs a method and a path"),
+    };
+    let method = Method::from_bytes(method.as_bytes())?;
+    let mut params = Vec::new();
+    let mut body = None;
+
+    while let Some(arg) = args.next() {
+        if arg == "--data" {
+            let data = args.next().ok_or_else(|| format_err!("--data needs a value"))?;
+            body = Some(serde_json::from_str::<Value>(data)?);
+            continue;
+        }
+        let mut kv = arg.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(k), Some(v)) => params.push((k.to_string(), v.to_string(), false)),
+            _ => bail!("Parameters must be KEY=VALUE"),
+        }
+    }
+
+    let res: Value = oqa.request(method, path, params, body.as_ref()).wait()?;
+    print_json(&res)
+}
+
+fn run() -> Result<(), Error> {
+    let opts = parse_opts()?;
+    let oqa = OpenQA::with_conf_file(&opts.config, &opts.host)?;
+
+    match opts.args[0].as_str() {
+        "test-suites" | "machines" | "products" | "job-templates" => table(&oqa, &opts),
+        "api" => api(&oqa, &opts),
+        c => bail!("Unknown command {}", c),
+    }
+}
+
+fn main() {
+    rt::run(rt::lazy(|| {
+        if let Err(e) = run() {
+            eprintln!("Error: {}\nTry openqa-rs --help", e);
+            process::exit(1);
+        }
+        future::ok(())
+    }));
+}
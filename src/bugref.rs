@@ -0,0 +1,169 @@
+use std::fmt;
+use std::str::FromStr;
+
+use failure::Error;
+
+/// A bug reference or label found in a job or group comment.
+///
+/// openQA recognises these in comment text to link jobs with bugs and to
+/// carry over labels between builds.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BugRef {
+    /// SUSE Bugzilla, `bsc#1234`
+    Bsc(u32),
+    /// SUSE Bugzilla by its older name, `bnc#1234`
+    Bnc(u32),
+    /// openSUSE Bugzilla, `boo#1234`
+    Boo(u32),
+    /// openSUSE Redmine, `poo#1234`
+    Poo(u32),
+    /// GitHub issue or pull request, `gh#owner/repo#1234`
+    Gh { repo: String, id: u32 },
+    /// Any other tracker openQA knows about, e.g. `jsc#SLE-1234`
+    Other { tracker: String, id: String },
+    /// `label:force_result:<result>` with an optional trailing reason
+    ForceResult { result: String, reason: Option<String> },
+    /// Any other `label:<name>`
+    Label(String),
+}
+
+const TRACKERS: &[&str] = &[
+    "bug", "bnc", "bsc", "boo", "bgo", "brc", "bko", "poo", "gh", "kde",
+    "fdo", "jsc", "pio", "lp",
+];
+
+fn parse_id(id: &str) -> Result<u32, Error> {
+    id.parse().map_err(|e| format_err!("Invalid bug id '{}': {}", id, e))
+}
+
+impl FromStr for BugRef {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<BugRef, Error> {
+        if let Some(label) = s.strip_prefix("label:") {
+            if label.is_empty() {
+                bail!("Empty label");
+            }
+            return Ok(match label.strip_prefix("force_result:") {
+                Some(res) => {
+                    let mut parts = res.splitn(2, ':');
+                    let result = parts.next().unwrap_or_default();
+                    if result.is_empty() {
+                        bail!("force_result label is missing a result: '{}'", s);
+                    }
+                    BugRef::ForceResult {
+                        result: result.to_string(),
+                        reason: parts.next().map(|r| r.to_string()),
+                    }
+                },
+                None => BugRef::Label(label.to_string()),
+            });
+        }
+
+        let (tracker, id) = match s.find('#') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => bail!("Not a bug reference: '{}'", s),
+        };
+        if id.is_empty() || !TRACKERS.contains(&tracker) {
+            bail!("Not a bug reference: '{}'", s);
+        }
+
+        Ok(match tracker {
+            "bsc" => BugRef::Bsc(parse_id(id)?),
+            "bnc" => BugRef::Bnc(parse_id(id)?),
+            "boo" => BugRef::Boo(parse_id(id)?),
+            "poo" => BugRef::Poo(parse_id(id)?),
+            "gh" => {
+                let i = id.rfind('#')
+                    .ok_or_else(|| format_err!("GitHub ref has no issue number: '{}'", s))?;
+                BugRef::Gh {
+                    repo: id[..i].to_string(),
+                    id: parse_id(&id[i + 1..])?,
+                }
+            },
+            _ => BugRef::Other {
+                tracker: tracker.to_string(),
+                id: id.to_string(),
+            },
+        })
+    }
+}
+
+impl fmt::Display for BugRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BugRef::Bsc(id) => write!(f, "bsc#{}", id),
+            BugRef::Bnc(id) => write!(f, "bnc#{}", id),
+            BugRef::Boo(id) => write!(f, "boo#{}", id),
+            BugRef::Poo(id) => write!(f, "poo#{}", id),
+            BugRef::Gh { repo, id } => write!(f, "gh#{}#{}", repo, id),
+            BugRef::Other { tracker, id } => write!(f, "{}#{}", tracker, id),
+            BugRef::ForceResult { result, reason: Some(r) } => {
+                write!(f, "label:force_result:{}:{}", result, r)
+            },
+            BugRef::ForceResult { result, reason: None } => {
+                write!(f, "label:force_result:{}", result)
+            },
+            BugRef::Label(l) => write!(f, "label:{}", l),
+        }
+    }
+}
+
+/// Extract all bug references and labels from a comment's text
+///
+/// Like openQA, a reference must start at the beginning of a word. Trailing
+/// punctuation is ignored and anything which doesn't parse is skipped.
+pub fn parse_bugrefs(text: &str) -> Vec<BugRef> {
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .map(|w| w.trim_end_matches(|c: char| ".;:!?)]\"'".contains(c)))
+        .filter(|w| !w.is_empty())
+        .filter_map(|w| w.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_single() {
+        assert_eq!(BugRef::Bsc(1234), "bsc#1234".parse().unwrap());
+        assert_eq!(BugRef::Bnc(1234), "bnc#1234".parse().unwrap());
+        assert_eq!(BugRef::Poo(42), "poo#42".parse().unwrap());
+        assert_eq!(BugRef::Gh { repo: "os-autoinst/openQA".to_string(), id: 7 },
+                   "gh#os-autoinst/openQA#7".parse().unwrap());
+        assert_eq!(BugRef::Other { tracker: "jsc".to_string(), id: "SLE-1".to_string() },
+                   "jsc#SLE-1".parse().unwrap());
+        assert_eq!(BugRef::ForceResult { result: "softfailed".to_string(),
+                                         reason: Some("bsc#1".to_string()) },
+                   "label:force_result:softfailed:bsc#1".parse().unwrap());
+        assert_eq!(BugRef::Label("wip".to_string()), "label:wip".parse().unwrap());
+
+        assert!("foo#1".parse::<BugRef>().is_err());
+        assert!("bsc#".parse::<BugRef>().is_err());
+        assert!("bsc#abc".parse::<BugRef>().is_err());
+        assert!("gh#1".parse::<BugRef>().is_err());
+        assert!("label:force_result:".parse::<BugRef>().is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        for s in &["bsc#1", "bnc#1", "boo#2", "poo#3", "gh#a/b#4", "jsc#SLE-5",
+                   "label:force_result:failed", "label:force_result:passed:why",
+                   "label:linked"] {
+            assert_eq!(*s, s.parse::<BugRef>().unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn parse_text() {
+        let text = "Known issue bsc#1100, see also poo#35 (gh#foo/bar#9).\n\
+                    label:force_result:softfailed:bsc#1100 not#1 xbsc#2";
+        assert_eq!(vec![
+            BugRef::Bsc(1100),
+            BugRef::Poo(35),
+            BugRef::ForceResult { result: "softfailed".to_string(),
+                                  reason: Some("bsc#1100".to_string()) },
+        ], parse_bugrefs(text));
+    }
+}
@@ -0,0 +1,148 @@
+//! Run many requests concurrently with a limit on how many are in flight
+//!
+//! The requests are futures, which do nothing until polled, so they can all
+//! be created up front with e.g. `templates.iter().map(|t| oqa.new_job_template(t))`.
+//! Requests made with an `OpenQA` client still respect its rate limit.
+
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::{future, stream, Future, IntoFuture, Stream};
+use failure::Error;
+
+/// What to do when a request fails
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnError {
+    /// Start no more requests, those already in flight are still finished
+    Stop,
+    /// Run every request and report the failures at the end
+    Continue,
+}
+
+/// The result of one request
+#[derive(Debug)]
+pub enum Outcome<T> {
+    Done(T),
+    Failed(Error),
+    /// Not started because an earlier request failed
+    Skipped,
+}
+
+impl<T> Outcome<T> {
+    pub fn is_done(&self) -> bool {
+        matches!(self, Outcome::Done(_))
+    }
+}
+
+/// The outcomes of a bulk run, in the same order as the requests
+#[derive(Debug)]
+pub struct Report<T> {
+    pub outcomes: Vec<Outcome<T>>,
+}
+
+impl<T> Report<T> {
+    pub fn is_ok(&self) -> bool {
+        self.outcomes.iter().all(|o| o.is_done())
+    }
+
+    /// The index and error of each failed request
+    pub fn errors(&self) -> impl Iterator<Item=(usize, &Error)> {
+        self.outcomes.iter().enumerate().filter_map(|(i, o)| match o {
+            Outcome::Failed(e) => Some((i, e)),
+            _ => None,
+        })
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.outcomes.iter().filter(|o| matches!(o, Outcome::Skipped)).count()
+    }
+
+    /// All of the results or the first error
+    pub fn into_result(self) -> Result<Vec<T>, Error> {
+        let mut res = Vec::with_capacity(self.outcomes.len());
+        for (i, o) in self.outcomes.into_iter().enumerate() {
+            match o {
+                Outcome::Done(v) => res.push(v),
+                Outcome::Failed(e) => return Err(format_err!("Request {}: {}", i, e)),
+                Outcome::Skipped => bail!("Request {} was skipped", i),
+            }
+        }
+        Ok(res)
+    }
+}
+
+impl<T> fmt::Display for Report<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let done = self.outcomes.iter().filter(|o| o.is_done()).count();
+        let failed = self.errors().count();
+
+        write!(f, "{} done, {} failed, {} skipped", done, failed, self.skipped())
+    }
+}
+
+/// Run `requests` with at most `limit` of them in flight at once
+pub fn run<I, F, T>(limit: usize, on_error: OnError, requests: I)
+                    -> impl Future<Item=Report<T>, Error=Error>
+where
+    I: IntoIterator<Item=F>,
+    F: IntoFuture<Item=T, Error=Error>,
+{
+    let requests: Vec<_> = requests.into_iter().map(IntoFuture::into_future).collect();
+    let outcomes = (0..requests.len()).map(|_| Outcome::Skipped).collect();
+    let failed = Arc::new(AtomicBool::new(false));
+    let stop = failed.clone();
+
+    stream::iter_ok(requests.into_iter().enumerate())
+        .take_while(move |_| Ok(!(on_error == OnError::Stop && stop.load(Ordering::SeqCst))))
+        .map(|(i, req)| req.then(move |res| Ok((i, res))))
+        .buffer_unordered(limit.max(1))
+        .fold(outcomes, move |mut outcomes: Vec<Outcome<T>>, (i, res)| {
+            outcomes[i] = match res {
+                Ok(v) => Outcome::Done(v),
+                Err(e) => {
+                    failed.store(true, Ordering::SeqCst);
+                    Outcome::Failed(e)
+                },
+            };
+            future::ok::<_, Error>(outcomes)
+        })
+        .map(|outcomes| Report { outcomes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requests(fail: usize) -> Vec<future::FutureResult<usize, Error>> {
+        (0..5).map(|i| if i == fail {
+            future::err(format_err!("{} failed", i))
+        } else {
+            future::ok(i)
+        }).collect()
+    }
+
+    #[test]
+    fn continue_on_error() {
+        let report = run(2, OnError::Continue, requests(1)).wait().unwrap();
+
+        assert!(!report.is_ok());
+        assert_eq!(vec![1], report.errors().map(|(i, _)| i).collect::<Vec<_>>());
+        assert_eq!("4 done, 1 failed, 0 skipped", report.to_string());
+        assert_eq!("Request 1: 1 failed", report.into_result().unwrap_err().to_string());
+
+        let report = ::OpenQA::default().bulk(2, OnError::Continue, requests(9)).wait().unwrap();
+        assert_eq!(vec![0, 1, 2, 3, 4], report.into_result().unwrap());
+    }
+
+    #[test]
+    fn stop_on_error() {
+        fn is_send<T: Send>(t: T) -> T { t }
+
+        let report = is_send(run(1, OnError::Stop, requests(1))).wait().unwrap();
+
+        assert!(report.outcomes[0].is_done());
+        assert_eq!(3, report.skipped());
+        assert_eq!("1 done, 1 failed, 3 skipped", report.to_string());
+    }
+}
@@ -0,0 +1,243 @@
+//! Cache GET response bodies in memory and optionally on disk
+//!
+//! Entries are keyed by URL so each host gets its own entries. On disk they
+//! are stored as `<dir>/<host:port>/<path and query>` with the names percent
+//! encoded.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use http::uri::Uri;
+
+struct Entry {
+    time: SystemTime,
+    body: Vec<u8>,
+}
+
+pub struct Cache {
+    ttl: Duration,
+    dir: Option<PathBuf>,
+    mem: Mutex<HashMap<String, Entry>>,
+}
+
+fn fresh(time: SystemTime, ttl: Duration) -> bool {
+    time.elapsed().map(|age| age < ttl).unwrap_or(false)
+}
+
+fn file_name(path: &str) -> String {
+    let mut name = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            b'0' ..= b'9' | b'A' ..= b'Z' | b'a' ..= b'z' | b'-' | b'_' | b'.' => {
+                name.push(b as char)
+            },
+            _ => name.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    name
+}
+
+impl Cache {
+    /// Keep responses in memory for `ttl`
+    pub fn memory(ttl: Duration) -> Cache {
+        Cache {
+            ttl,
+            dir: None,
+            mem: Mutex::default(),
+        }
+    }
+
+    /// Keep responses in memory and under `dir` for `ttl`, so they survive
+    /// between runs
+    pub fn disk<P: Into<PathBuf>>(dir: P, ttl: Duration) -> Cache {
+        Cache {
+            ttl,
+            dir: Some(dir.into()),
+            mem: Mutex::default(),
+        }
+    }
+
+    /// The path of a URL relative to the API base, e.g. "test_suites"
+    fn api_path(uri: &Uri) -> String {
+        let mut path = uri.path().splitn(4, '/').nth(3).unwrap_or_default().to_string();
+        if let Some(q) = uri.query() {
+            path.push('?');
+            path.push_str(q);
+        }
+        path
+    }
+
+    fn host_dir(&self, uri: &Uri) -> Option<PathBuf> {
+        self.dir.as_ref().map(|d| {
+            let host = uri.authority_part().map(|a| a.as_str()).unwrap_or_default();
+            d.join(file_name(host))
+        })
+    }
+
+    pub fn get(&self, uri: &Uri) -> Option<Vec<u8>> {
+        let key = uri.to_string();
+        {
+            let mut mem = self.mem.lock().unwrap();
+            match mem.get(&key) {
+                Some(e) if fresh(e.time, self.ttl) => return Some(e.body.clone()),
+                Some(_) => { mem.remove(&key); },
+                None => (),
+            }
+        }
+
+        let file = self.host_dir(uri)?.join(file_name(&Cache::api_path(uri)));
+        let time = fs::metadata(&file).and_then(|m| m.modified()).ok()?;
+        if !fresh(time, self.ttl) {
+            return None;
+        }
+        let body = fs::read(&file).ok()?;
+        self.mem.lock().unwrap().insert(key, Entry { time, body: body.clone() });
+        Some(body)
+    }
+
+    pub fn put(&self, uri: &Uri, body: &[u8]) {
+        self.mem.lock().unwrap().insert(uri.to_string(), Entry {
+            time: SystemTime::now(),
+            body: body.to_vec(),
+        });
+
+        if let Some(dir) = self.host_dir(uri) {
+            let res = fs::create_dir_all(&dir)
+                .and_then(|_| fs::File::create(dir.join(file_name(&Cache::api_path(uri)))))
+                .and_then(|mut f| f.write_all(body));
+            if let Err(e) = res {
+                warn!("Failed to write cache entry for {}: {}", uri, e);
+            }
+        }
+    }
+
+    /// Drop all entries for the host of `base` whose API path starts with
+    /// `prefix`, e.g. "test_suites"
+    pub fn invalidate(&self, base: &Uri, prefix: &str) {
+        let mut start = base.to_string();
+        start.push_str(prefix);
+        self.mem.lock().unwrap().retain(|k, _| !k.starts_with(&start));
+
+        if let Some(dir) = self.host_dir(base) {
+            let prefix = file_name(prefix);
+            let res = fs::read_dir(&dir).and_then(|entries| {
+                for e in entries {
+                    let e = e?;
+                    if e.file_name().to_string_lossy().starts_with(&prefix) {
+                        fs::remove_file(e.path())?;
+                    }
+                }
+                Ok(())
+            });
+            match res {
+                Err(ref e) if e.kind() != io::ErrorKind::NotFound => {
+                    warn!("Failed to invalidate cache in {}: {}", dir.display(), e);
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::thread;
+
+    fn uri(s: &str) -> Uri {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn memory() {
+        let cache = Cache::memory(Duration::from_secs(60));
+        let base = uri("https://a.example/api/v1/");
+
+        cache.put(&uri("https://a.example/api/v1/test_suites"), b"1");
+        cache.put(&uri("https://a.example/api/v1/machines"), b"2");
+        cache.put(&uri("https://b.example/api/v1/test_suites"), b"3");
+
+        assert_eq!(Some(b"1".to_vec()), cache.get(&uri("https://a.example/api/v1/test_suites")));
+        cache.invalidate(&base, "test_suites");
+        assert_eq!(None, cache.get(&uri("https://a.example/api/v1/test_suites")));
+        assert_eq!(Some(b"2".to_vec()), cache.get(&uri("https://a.example/api/v1/machines")));
+        assert_eq!(Some(b"3".to_vec()), cache.get(&uri("https://b.example/api/v1/test_suites")));
+
+        let cache = Cache::memory(Duration::from_millis(10));
+        cache.put(&uri("https://a.example/api/v1/machines"), b"2");
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(None, cache.get(&uri("https://a.example/api/v1/machines")));
+    }
+
+    #[test]
+    fn disk() {
+        let dir = env::temp_dir().join(format!("openqa-cache-test-{}", ::std::process::id()));
+        let base = uri("http://localhost:9526/api/v1/");
+        let jobs = uri("http://localhost:9526/api/v1/jobs?ids=1&ids=2");
+
+        Cache::disk(&dir, Duration::from_secs(60)).put(&jobs, b"{}");
+        assert!(dir.join("localhost%3A9526").join("jobs%3Fids%3D1%26ids%3D2").exists());
+
+        let cache = Cache::disk(&dir, Duration::from_secs(60));
+        assert_eq!(Some(b"{}".to_vec()), cache.get(&jobs));
+        cache.invalidate(&base, "jobs");
+        assert_eq!(None, Cache::disk(&dir, Duration::from_secs(60)).get(&jobs));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn client() {
+        use std::sync::{Arc, Mutex};
+        use bytes::Bytes;
+        use futures::{future, Future};
+        use http;
+        use hyper::Chunk;
+        use transport::{ResponseFuture, Transport};
+        use OpenQA;
+
+        /// Answers every request with an empty list or a successful delete
+        #[derive(Default)]
+        struct Counter {
+            sent: Mutex<Vec<String>>,
+        }
+
+        impl Transport for Counter {
+            fn send(&self, req: http::Request<Bytes>) -> ResponseFuture {
+                let body = match *req.method() {
+                    http::Method::DELETE => r#"{"result": 1}"#,
+                    _ if req.uri().path().ends_with("machines") => r#"{"Machines": []}"#,
+                    _ => r#"{"jobs": []}"#,
+                };
+                self.sent.lock().unwrap().push(req.uri().path().to_string());
+                let (parts, _) = http::Response::new(()).into_parts();
+                Box::new(future::ok((parts, Chunk::from(body))))
+            }
+        }
+
+        let counter = Arc::new(Counter::default());
+        let oqa = OpenQA::new("http://counter", "", "")
+            .with_transport(counter.clone())
+            .with_cache(Cache::memory(Duration::from_secs(60)));
+        let sent = || counter.sent.lock().unwrap().len();
+
+        oqa.get_machines().wait().unwrap();
+        oqa.get_machines().wait().unwrap();
+        assert_eq!(1, sent());
+        oqa.get_jobs(&[1]).wait().unwrap();
+        oqa.get_jobs(&[1]).wait().unwrap();
+        assert_eq!(3, sent());
+
+        let delete = oqa.del_machine(1);
+        oqa.get_machines().wait().unwrap();
+        assert_eq!(3, sent());
+        delete.wait().unwrap();
+        oqa.get_machines().wait().unwrap();
+        assert_eq!(5, sent());
+    }
+}
@@ -0,0 +1,119 @@
+//! Copy the configuration of one openQA instance to another
+//!
+//! `export` produces a `sync::State` in the same shape as openQA's
+//! `dump_templates` output, without any ids. Job groups are written with
+//! `group_name` and their YAML `template`, if it is known. Job templates only refer to
+//! their group, machine, test suite and product by name, so `import` can
+//! recreate everything on an instance where the ids differ.
+
+use futures::{future, Future};
+use failure::Error;
+
+use sync::{Plan, State};
+use {JobGroup, Machine, OpenQA, Product, Settings, TestSuite};
+
+/// Remove everything which is specific to the instance the state came from
+pub fn strip_ids(state: State) -> State {
+    State {
+        job_groups: state.job_groups.into_iter()
+            .map(|g| JobGroup { id: 0, parent_id: None, ..g })
+            .collect(),
+        machines: state.machines.into_iter()
+            .map(|m| Machine { id: 0, ..m })
+            .collect(),
+        products: state.products.into_iter()
+            .map(|p| Product { id: 0, ..p })
+            .collect(),
+        test_suites: state.test_suites.into_iter()
+            .map(|t| TestSuite { id: 0, ..t })
+            .collect(),
+        job_templates: state.job_templates.into_iter()
+            .map(|mut t| {
+                t.id = 0;
+                t.machine = Machine {
+                    id: 0,
+                    name: t.machine.name,
+                    backend: String::new(),
+                    settings: Settings::new(),
+                };
+                t.test_suite = TestSuite {
+                    id: 0,
+                    name: t.test_suite.name,
+                    description: String::new(),
+                    settings: Settings::new(),
+                };
+                t.product = Product { id: 0, settings: Settings::new(), ..t.product };
+                t
+            })
+            .collect(),
+    }
+}
+
+/// Fetch the configuration of an instance, ready to be saved with
+/// `State::to_file`
+pub fn export(oqa: &OpenQA) -> impl Future<Item=State, Error=Error> {
+    State::fetch(oqa).map(strip_ids)
+}
+
+/// Create whatever in `state` is missing on the instance and update what
+/// differs. Nothing is deleted.
+///
+/// With `dry_run` set the plan is only computed. Either way the plan is
+/// returned so it can be shown to the user.
+pub fn import<'a>(oqa: &'a OpenQA, state: State, dry_run: bool)
+                  -> impl Future<Item=Plan, Error=Error> + 'a
+{
+    State::fetch(oqa).and_then(move |live| {
+        let plan = Plan::new(&state, &live, false);
+
+        if dry_run {
+            future::Either::A(future::ok(plan))
+        } else {
+            future::Either::B(plan.clone().apply(oqa).map(move |_| plan))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{self, Value};
+
+    #[test]
+    fn stripped() {
+        let live = State::from_json(r#"{
+            "JobGroups": [{"id": 7, "name": "Kernel", "parent_id": 2,
+                           "build_version_sort": 1, "carry_over_bugrefs": 0,
+                           "template": "products: {}"}],
+            "JobTemplates": [
+                {"id": 5, "group_name": "Kernel", "prio": 50,
+                 "machine": {"id": 1, "name": "64bit", "backend": "qemu"},
+                 "product": {"id": 3, "distri": "sle", "version": "15", "flavor": "DVD",
+                             "arch": "x86_64", "group": "sle"},
+                 "test_suite": {"id": 4, "name": "ltp", "description": "LTP"}}
+            ]
+        }"#).unwrap();
+
+        let state = strip_ids(live);
+        let json = serde_json::to_value(&state).unwrap();
+        let expected: Value = serde_json::from_str(r#"{
+            "JobGroups": [{"group_name": "Kernel", "build_version_sort": true,
+                           "carry_over_bugrefs": false, "template": "products: {}"}],
+            "Machines": [],
+            "Products": [],
+            "TestSuites": [],
+            "JobTemplates": [
+                {"group_name": "Kernel", "prio": 50,
+                 "machine": {"name": "64bit"},
+                 "product": {"distri": "sle", "version": "15", "flavor": "DVD",
+                             "arch": "x86_64"},
+                 "test_suite": {"name": "ltp"}}
+            ]
+        }"#).unwrap();
+        assert_eq!(expected, json);
+
+        let again = State::from_json(&state.to_json().unwrap()).unwrap();
+        assert_eq!("Kernel", again.job_groups[0].name);
+        assert_eq!(Some("products: {}"), again.job_groups[0].template.as_deref());
+    }
+}
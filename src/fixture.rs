@@ -0,0 +1,310 @@
+//! Record responses to a file and replay them later without a server
+//!
+//! A fixture file is a JSON list of request/response pairs. Requests are
+//! matched on their method, path and query, ignoring the host, so fixtures
+//! recorded from a public instance can be replayed anywhere. Requests which
+//! are made more than once get the recorded responses in order, the last
+//! one being repeated.
+//!
+//! The API key, hash and time are removed from recorded requests as are
+//! cookies from responses.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use futures::{future, Future};
+use http::{self, Method};
+use hyper::Chunk;
+use serde_json;
+use failure::Error;
+
+use middleware::redacted;
+use transport::{ResponseFuture, Transport, UpgradeFuture};
+
+/// A recorded request and its response
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Exchange {
+    pub method: String,
+    /// The path and query, e.g. "/api/v1/jobs?ids=1"
+    pub path: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub request_headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub request_body: String,
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+fn path_and_query(uri: &http::Uri) -> String {
+    uri.path_and_query().map(|p| p.as_str()).unwrap_or("/").to_string()
+}
+
+enum Mode {
+    Record,
+    Replay {
+        /// How many times each request has been replayed
+        served: HashMap<(String, String), usize>,
+    },
+}
+
+pub struct Fixtures {
+    path: PathBuf,
+    mode: Mutex<Mode>,
+    exchanges: Mutex<Vec<Exchange>>,
+}
+
+impl Fixtures {
+    /// Send requests as normal and write them with their responses to `path`
+    ///
+    /// The file is rewritten after each response.
+    pub fn record<P: Into<PathBuf>>(path: P) -> Fixtures {
+        Fixtures {
+            path: path.into(),
+            mode: Mutex::new(Mode::Record),
+            exchanges: Mutex::default(),
+        }
+    }
+
+    /// Answer requests from the fixtures in `path` instead of sending them
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Fixtures, Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| format_err!("Reading fixtures {}: {}", path.display(), e))?;
+        let exchanges = serde_json::from_str(&text)
+            .map_err(|e| format_err!("Parsing fixtures {}: {}", path.display(), e))?;
+
+        Ok(Fixtures {
+            path: path.to_path_buf(),
+            mode: Mutex::new(Mode::Replay { served: HashMap::new() }),
+            exchanges: Mutex::new(exchanges),
+        })
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        match *self.mode.lock().unwrap() {
+            Mode::Replay { .. } => true,
+            Mode::Record => false,
+        }
+    }
+
+    /// Save a request and its response if recording
+    pub fn save(&self,
+                req: &http::request::Parts,
+                req_body: Option<&[u8]>,
+                res: &http::response::Parts,
+                body: &[u8]) -> Result<(), Error>
+    {
+        if self.is_replaying() {
+            return Ok(());
+        }
+
+        let mut exchanges = self.exchanges.lock().unwrap();
+        exchanges.push(Exchange {
+            method: req.method.to_string(),
+            path: path_and_query(&req.uri),
+            request_headers: redacted(&req.headers),
+            request_body: req_body.map(|b| String::from_utf8_lossy(b).into_owned())
+                .unwrap_or_default(),
+            status: res.status.as_u16(),
+            headers: redacted(&res.headers),
+            body: String::from_utf8_lossy(body).into_owned(),
+        });
+
+        let json = serde_json::to_string_pretty(&*exchanges)?;
+        fs::write(&self.path, json)
+            .map_err(|e| format_err!("Writing fixtures {}: {}", self.path.display(), e))
+    }
+
+    /// The recorded response to a request
+    pub fn find(&self, method: &Method, uri: &http::Uri)
+                -> Result<(http::response::Parts, Chunk), Error>
+    {
+        let key = (method.to_string(), path_and_query(uri));
+        let exchanges = self.exchanges.lock().unwrap();
+        let matches: Vec<&Exchange> = exchanges.iter()
+            .filter(|e| e.method == key.0 && e.path == key.1)
+            .collect();
+        if matches.is_empty() {
+            bail!("No fixture for {} {} in {}", key.0, key.1, self.path.display());
+        }
+
+        let n = match *self.mode.lock().unwrap() {
+            Mode::Replay { ref mut served } => {
+                let n = served.entry(key).or_insert(0);
+                *n += 1;
+                *n - 1
+            },
+            Mode::Record => 0,
+        };
+        let ex = matches[n.min(matches.len() - 1)];
+
+        let mut res = http::Response::builder();
+        res.status(ex.status);
+        for (k, v) in &ex.headers {
+            res.header(k.as_str(), v.as_str());
+        }
+        let (parts, _) = res.body(())?.into_parts();
+        Ok((parts, Chunk::from(ex.body.clone())))
+    }
+}
+
+/// A transport which records or replays the requests sent through it
+pub struct Recorder<T> {
+    inner: T,
+    fixtures: Arc<Fixtures>,
+}
+
+impl<T: Transport> Recorder<T> {
+    pub fn new<F: Into<Arc<Fixtures>>>(inner: T, fixtures: F) -> Recorder<T> {
+        Recorder { inner, fixtures: fixtures.into() }
+    }
+}
+
+impl<T: Transport> Transport for Recorder<T> {
+    fn send(&self, req: http::Request<Bytes>) -> ResponseFuture {
+        if self.fixtures.is_replaying() {
+            return Box::new(future::result(self.fixtures.find(req.method(), req.uri())));
+        }
+
+        let fixtures = self.fixtures.clone();
+        let body = req.body().clone();
+        let mut sent = http::Request::new(());
+        *sent.method_mut() = req.method().clone();
+        *sent.uri_mut() = req.uri().clone();
+        *sent.headers_mut() = req.headers().clone();
+        let sent = sent.into_parts().0;
+
+        Box::new(self.inner.send(req).map(move |res| {
+            if let Err(e) = fixtures.save(&sent, Some(&body[..]), &res.0, &res.1) {
+                warn!("{}", e);
+            }
+            res
+        }))
+    }
+
+    /// Upgraded connections aren't recorded, and can't be replayed
+    fn upgrade(&self, req: http::Request<Bytes>) -> UpgradeFuture {
+        if self.fixtures.is_replaying() {
+            return Box::new(future::err(format_err!("Can't replay an upgrade to {}",
+                                                    req.uri())));
+        }
+        self.inner.upgrade(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use middleware::REDACTED;
+
+    fn request(method: Method, uri: &str) -> http::request::Parts {
+        http::Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("X-API-Key", "1234")
+            .header("X-API-Hash", "abcd")
+            .header("Accept", "application/json")
+            .body(()).unwrap().into_parts().0
+    }
+
+    fn response(status: u16, link: Option<&str>) -> http::response::Parts {
+        let mut res = http::Response::builder();
+        res.status(status).header("Set-Cookie", "session=x");
+        if let Some(l) = link {
+            res.header("Link", l);
+        }
+        res.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn record_replay() {
+        let path = env::temp_dir().join(format!("openqa-fixtures-{}.json", ::std::process::id()));
+        let rec = Fixtures::record(&path);
+        let get = request(Method::GET, "https://o3.example/api/v1/jobs?limit=1");
+        let post = request(Method::POST, "https://o3.example/api/v1/machines?name=a");
+
+        rec.save(&get, None, &response(200, Some("</api/v1/jobs?offset=1>; rel=\"next\"")),
+                 b"{\"jobs\":[1]}").unwrap();
+        rec.save(&post, None, &response(200, None), b"{\"id\":1}").unwrap();
+        rec.save(&post, None, &response(400, None), b"{\"error\":\"exists\"}").unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(!text.contains("1234") && !text.contains("abcd") && !text.contains("session"));
+        assert!(text.contains(REDACTED));
+
+        let rep = Fixtures::replay(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(rep.is_replaying());
+
+        let uri = "http://localhost/api/v1/jobs?limit=1".parse().unwrap();
+        let (parts, body) = rep.find(&Method::GET, &uri).unwrap();
+        assert_eq!(200, parts.status.as_u16());
+        assert!(parts.headers.contains_key("link"));
+        assert_eq!(&b"{\"jobs\":[1]}"[..], &body[..]);
+
+        let uri = "http://localhost/api/v1/machines?name=a".parse().unwrap();
+        assert_eq!(200, rep.find(&Method::POST, &uri).unwrap().0.status.as_u16());
+        assert_eq!(400, rep.find(&Method::POST, &uri).unwrap().0.status.as_u16());
+        assert_eq!(400, rep.find(&Method::POST, &uri).unwrap().0.status.as_u16());
+
+        let uri = "http://localhost/api/v1/machines".parse().unwrap();
+        assert!(rep.find(&Method::GET, &uri).is_err());
+    }
+
+    struct Ok;
+
+    impl Transport for Ok {
+        fn send(&self, _: http::Request<Bytes>) -> ResponseFuture {
+            let (parts, _) = http::Response::new(()).into_parts();
+            Box::new(future::ok((parts, Chunk::from("{}"))))
+        }
+    }
+
+    #[test]
+    fn transport_after_fixtures() {
+        use UserAgent;
+
+        let path = env::temp_dir().join(format!("openqa-order-{}.json", ::std::process::id()));
+        let mut ua = UserAgent::new("http://fake", "key", "secret");
+        ua.set_fixtures(Fixtures::record(&path));
+        ua.set_transport(Ok);
+        ua.request(Method::GET, ua.url("machines"), None).wait().unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(text.contains("/api/v1/machines"));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn mock_roundtrip() {
+        use futures::Future;
+        use mock::MockServer;
+        use {Machine, OpenQA, Settings};
+
+        let path = env::temp_dir().join(format!("openqa-mock-{}.json", ::std::process::id()));
+        let mut server = MockServer::start().unwrap();
+        server.add_machine(Machine {
+            id: 0,
+            name: "64bit".to_string(),
+            backend: "qemu".to_string(),
+            settings: Settings::new(),
+        });
+
+        let oqa = server.client().with_fixtures(Fixtures::record(&path));
+        let live = server.block_on(oqa.get_machines()).unwrap();
+        drop(server);
+
+        let oqa = OpenQA::new("http://nowhere.invalid", "", "")
+            .with_fixtures(Fixtures::replay(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+        let replayed = oqa.get_machines().wait().unwrap();
+        assert_eq!(live.machines[0].name, replayed.machines[0].name);
+    }
+}
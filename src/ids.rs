@@ -0,0 +1,122 @@
+//! Map the names of test suites, machines, products and job groups to ids
+//!
+//! Ids differ between openQA instances, names (mostly) don't.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use failure::Error;
+
+use {JobGroup, Machine, Product, TestSuite};
+
+/// The unique key of a product
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ProductKey {
+    pub distri: String,
+    pub version: String,
+    pub flavor: String,
+    pub arch: String,
+}
+
+impl ProductKey {
+    pub fn new<D, V, F, A>(distri: D, version: V, flavor: F, arch: A) -> ProductKey
+    where
+        D: Into<String>,
+        V: Into<String>,
+        F: Into<String>,
+        A: Into<String>,
+    {
+        ProductKey {
+            distri: distri.into(),
+            version: version.into(),
+            flavor: flavor.into(),
+            arch: arch.into(),
+        }
+    }
+}
+
+impl<'a> From<&'a Product> for ProductKey {
+    fn from(p: &'a Product) -> ProductKey {
+        ProductKey::new(p.distri.as_str(), p.version.as_str(), p.flavor.as_str(), p.arch.as_str())
+    }
+}
+
+impl fmt::Display for ProductKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}-{}-{}", self.distri, self.version, self.flavor, self.arch)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct IdMap {
+    test_suites: HashMap<String, i32>,
+    machines: HashMap<String, i32>,
+    products: HashMap<ProductKey, i32>,
+    groups: HashMap<String, i32>,
+}
+
+impl IdMap {
+    pub fn new(test_suites: &[TestSuite],
+               machines: &[Machine],
+               products: &[Product],
+               groups: &[JobGroup]) -> IdMap
+    {
+        IdMap {
+            test_suites: test_suites.iter().map(|t| (t.name.clone(), t.id)).collect(),
+            machines: machines.iter().map(|m| (m.name.clone(), m.id)).collect(),
+            products: products.iter().map(|p| (ProductKey::from(p), p.id)).collect(),
+            groups: groups.iter().map(|g| (g.name.clone(), g.id)).collect(),
+        }
+    }
+
+    pub fn test_suite(&self, name: &str) -> Result<i32, Error> {
+        self.test_suites.get(name).cloned()
+            .ok_or_else(|| format_err!("No test suite named '{}'", name))
+    }
+
+    pub fn machine(&self, name: &str) -> Result<i32, Error> {
+        self.machines.get(name).cloned()
+            .ok_or_else(|| format_err!("No machine named '{}'", name))
+    }
+
+    pub fn product(&self, key: &ProductKey) -> Result<i32, Error> {
+        self.products.get(key).cloned()
+            .ok_or_else(|| format_err!("No product {}", key))
+    }
+
+    pub fn group(&self, name: &str) -> Result<i32, Error> {
+        self.groups.get(name).cloned()
+            .ok_or_else(|| format_err!("No job group named '{}'", name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Settings;
+
+    #[test]
+    fn lookup() {
+        let ids = IdMap::new(
+            &[TestSuite { description: String::new(), id: 1, name: "ltp".to_string(),
+                          settings: Settings::new() }],
+            &[Machine { id: 2, name: "64bit".to_string(), backend: String::new(),
+                        settings: Settings::new() }],
+            &[Product { id: 3, distri: "sle".to_string(), version: "12-SP4".to_string(),
+                        flavor: "Server-DVD".to_string(), arch: "x86_64".to_string(),
+                        settings: Settings::new() }],
+            &[JobGroup { id: 4, name: "Kernel".to_string(), ..JobGroup::default() }],
+        );
+
+        assert_eq!(1, ids.test_suite("ltp").unwrap());
+        assert_eq!(2, ids.machine("64bit").unwrap());
+        assert_eq!(3, ids.product(&ProductKey::new("sle", "12-SP4", "Server-DVD", "x86_64"))
+                   .unwrap());
+        assert_eq!(4, ids.group("Kernel").unwrap());
+
+        assert!(ids.machine("ltp").is_err());
+        let err = ids.product(&ProductKey::new("sle", "15", "Server-DVD", "x86_64"))
+            .unwrap_err();
+        assert_eq!("No product sle-15-Server-DVD-x86_64", err.to_string());
+    }
+}
@@ -3,46 +3,102 @@ extern crate bytes;
 extern crate hyper;
 extern crate hyper_tls;
 extern crate serde;
+#[cfg_attr(feature = "mock", macro_use)]
 extern crate serde_json;
+extern crate serde_yaml;
 #[macro_use]
 extern crate serde_derive;
 extern crate futures;
 #[macro_use]
 extern crate failure;
 extern crate crypto;
+extern crate rand;
+extern crate base64;
+extern crate tungstenite;
 extern crate time;
 #[macro_use]
 extern crate log;
+extern crate tracing;
+extern crate tracing_futures;
 extern crate ini;
+extern crate tokio_timer;
+#[cfg(any(test, feature = "mock"))]
+extern crate tokio;
 
 pub mod user_agent;
+pub mod bugref;
+pub mod lint;
+pub mod settings;
+pub mod resolve;
+pub mod sync;
+pub mod export;
+pub mod ids;
+pub mod cache;
+pub mod page;
+pub mod bulk;
+pub mod rate;
+pub mod fixture;
+pub mod transport;
+pub mod middleware;
+pub mod live;
+pub mod wait;
+pub mod overview;
+pub mod results;
+pub mod scenario;
+pub mod stats;
+#[cfg(feature = "mock")]
+pub mod mock;
 
+use std::cmp;
 use std::path::Path;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 
+use serde::{Deserialize, Deserializer};
 use serde::de::DeserializeOwned;
-use bytes::BytesMut;
-use futures::future;
+use serde_json::Value;
+use bytes::{Bytes, BytesMut};
+use futures::{future, Stream};
 use hyper::rt::Future;
 use hyper::Chunk;
+use http::uri::Uri;
 use failure::Error;
 use ini::Ini;
 
 pub use user_agent::UserAgent;
+pub use bugref::{BugRef, parse_bugrefs};
+pub use settings::Settings;
+pub use ids::{IdMap, ProductKey};
+pub use cache::Cache;
+pub use bulk::{OnError, Outcome, Report};
+pub use rate::RateLimit;
+pub use fixture::Fixtures;
+pub use transport::Transport;
+pub use middleware::Middleware;
+pub use wait::WaitOptions;
+pub use scenario::Scenario;
+pub use http::Method;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Ids are left out of objects which don't exist on a server yet
+fn is_zero(id: &i32) -> bool {
+    *id == 0
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Setting {
     pub key: String,
     pub value: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TestSuite {
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub description: String,
+    #[serde(default, skip_serializing_if = "is_zero")]
     pub id: i32,
     pub name: String,
-    #[serde(default)]
-    pub settings: Vec<Setting>,
+    #[serde(default, skip_serializing_if = "Settings::is_empty")]
+    pub settings: Settings,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,15 +107,16 @@ pub struct TestSuites {
     pub test_suites: Vec<TestSuite>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Product {
+    #[serde(default, skip_serializing_if = "is_zero")]
     pub id: i32,
     pub arch: String,
     pub distri: String,
     pub flavor: String,
     pub version: String,
-    #[serde(default)]
-    pub settings: Vec<Setting>,
+    #[serde(default, skip_serializing_if = "Settings::is_empty")]
+    pub settings: Settings,
 }
 
 #[derive(Deserialize)]
@@ -68,14 +125,15 @@ pub struct Products {
     pub products: Vec<Product>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Machine {
+    #[serde(default, skip_serializing_if = "is_zero")]
     pub id: i32,
     pub name: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub backend: String,
-    #[serde(default)]
-    pub settings: Vec<Setting>,
+    #[serde(default, skip_serializing_if = "Settings::is_empty")]
+    pub settings: Settings,
 }
 
 #[derive(Deserialize)]
@@ -92,6 +150,15 @@ pub enum UpdateResult {
     Err(String),
 }
 
+impl UpdateResult {
+    pub fn into_result(self) -> Result<i32, Error> {
+        match self {
+            UpdateResult::Ok(r) => Ok(r),
+            UpdateResult::Err(e) => Err(format_err!("openQA: {}", e)),
+        }
+    }
+}
+
 #[derive(Deserialize)]
 pub enum CreateResult {
     #[serde(rename = "id")]
@@ -100,6 +167,19 @@ pub enum CreateResult {
     Err(String),
 }
 
+impl CreateResult {
+    pub fn into_result(self) -> Result<i32, Error> {
+        match self {
+            CreateResult::Ok(id) => Ok(id),
+            CreateResult::Err(e) => Err(format_err!("openQA: {}", e)),
+        }
+    }
+}
+
+fn default_prio() -> i32 {
+    50
+}
+
 pub struct JobTemplate {
     pub product_id: i32,
     pub machine_id: i32,
@@ -107,13 +187,34 @@ pub struct JobTemplate {
     pub test_suite_id: i32,
 }
 
-#[derive(Deserialize)]
+impl JobTemplate {
+    pub fn from_names(ids: &IdMap,
+                      group: &str,
+                      product: &ProductKey,
+                      machine: &str,
+                      test_suite: &str) -> Result<JobTemplate, Error>
+    {
+        Ok(JobTemplate {
+            product_id: ids.product(product)?,
+            machine_id: ids.machine(machine)?,
+            group_id: ids.group(group)?,
+            test_suite_id: ids.test_suite(test_suite)?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct JobTemplateInfo {
     pub group_name: String,
+    #[serde(default, skip_serializing_if = "is_zero")]
     pub id: i32,
     pub machine: Machine,
+    #[serde(default = "default_prio")]
     pub prio: i32,
+    pub product: Product,
     pub test_suite: TestSuite,
+    #[serde(default, skip_serializing_if = "Settings::is_empty")]
+    pub settings: Settings,
 }
 
 #[derive(Deserialize)]
@@ -122,8 +223,380 @@ pub struct JobTemplateInfos {
     pub job_templates: Vec<JobTemplateInfo>, 
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Comment {
+    pub id: i32,
+    pub text: String,
+    #[serde(rename = "userName", default)]
+    pub user_name: String,
+    #[serde(default)]
+    pub created: String,
+    #[serde(default)]
+    pub updated: String,
+    #[serde(rename = "renderedMarkdown", default)]
+    pub rendered_markdown: String,
+}
+
+impl Comment {
+    /// Bug references and labels contained in the comment text
+    pub fn bugrefs(&self) -> Vec<BugRef> {
+        parse_bugrefs(&self.text)
+    }
+}
+
+/// The object a comment is attached to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommentTarget {
+    Job(i32),
+    Group(i32),
+}
+
+impl CommentTarget {
+    fn path(&self) -> String {
+        match self {
+            CommentTarget::Job(id) => format!("jobs/{}/comments", id),
+            CommentTarget::Group(id) => format!("groups/{}/comments", id),
+        }
+    }
+}
+
+/// openQA returns the raw database columns for bugs, so flags may be 0/1
+fn bool_from_int<'de, D>(d: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum IntOrBool {
+        Int(i64),
+        Bool(bool),
+    }
+
+    Ok(match Option::<IntOrBool>::deserialize(d)? {
+        Some(IntOrBool::Int(i)) => i != 0,
+        Some(IntOrBool::Bool(b)) => b,
+        None => false,
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Bug {
+    #[serde(default)]
+    pub id: i32,
+    pub bugid: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub priority: Option<String>,
+    #[serde(default, deserialize_with = "bool_from_int")]
+    pub assigned: bool,
+    #[serde(default)]
+    pub assignee: Option<String>,
+    #[serde(default, deserialize_with = "bool_from_int")]
+    pub open: bool,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub resolution: Option<String>,
+    #[serde(default, deserialize_with = "bool_from_int")]
+    pub existing: bool,
+    #[serde(default, deserialize_with = "bool_from_int")]
+    pub refreshed: bool,
+    #[serde(default)]
+    pub t_created: Option<String>,
+    #[serde(default)]
+    pub t_updated: Option<String>,
+}
+
+impl Bug {
+    /// Parse `bugid` (e.g. "bsc#1234") so it can be matched against comments
+    pub fn bugref(&self) -> Result<BugRef, Error> {
+        self.bugid.parse()
+    }
+
+    fn params(&self) -> Vec<(&'static str, String, bool)> {
+        let flag = |b: bool| if b { "1" } else { "0" }.to_string();
+        let mut params = vec![
+            ("assigned", flag(self.assigned), false),
+            ("open", flag(self.open), false),
+            ("existing", flag(self.existing), false),
+        ];
+        let opts = [
+            ("title", &self.title),
+            ("priority", &self.priority),
+            ("assignee", &self.assignee),
+            ("status", &self.status),
+            ("resolution", &self.resolution),
+        ];
+        for (k, v) in opts.iter() {
+            if let Some(v) = v {
+                params.push((k, v.clone(), false));
+            }
+        }
+        params
+    }
+}
+
+/// Map of bug table ids to bug references (`bugid`)
+#[derive(Deserialize, Debug)]
+pub struct Bugs {
+    pub bugs: BTreeMap<i32, String>,
+}
+
+/// Which bugs to list with `OpenQA::get_bugs`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BugFilter {
+    All,
+    /// Refreshed bugs last updated more than the given number of seconds
+    /// ago, or an hour by default
+    Refreshable(Option<u64>),
+    /// Bugs created within the given number of seconds
+    CreatedWithin(u64),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct JobGroup {
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub id: i32,
+    /// `dump_templates` calls this `group_name`
+    #[serde(alias = "group_name")]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_priority: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_order: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size_limit_gb: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_logs_in_days: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_important_logs_in_days: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_results_in_days: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_important_results_in_days: Option<i32>,
+    #[serde(default, deserialize_with = "bool_from_int")]
+    pub build_version_sort: bool,
+    #[serde(default, deserialize_with = "bool_from_int")]
+    pub carry_over_bugrefs: bool,
+    /// The group's YAML schedule, as written by `dump_templates`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+impl JobGroup {
+    fn params(&self) -> Params {
+        let flag = |b: bool| if b { "1" } else { "0" }.to_string();
+        let mut params = vec![
+            ("name".to_string(), self.name.clone(), false),
+            ("build_version_sort".to_string(), flag(self.build_version_sort), false),
+            ("carry_over_bugrefs".to_string(), flag(self.carry_over_bugrefs), false),
+        ];
+        if let Some(ref d) = self.description {
+            params.push(("description".to_string(), d.clone(), false));
+        }
+        if let Some(p) = self.parent_id {
+            params.push(("parent_id".to_string(), p.to_string(), false));
+        }
+        let nums = [
+            ("default_priority", self.default_priority),
+            ("sort_order", self.sort_order),
+            ("size_limit_gb", self.size_limit_gb),
+            ("keep_logs_in_days", self.keep_logs_in_days),
+            ("keep_important_logs_in_days", self.keep_important_logs_in_days),
+            ("keep_results_in_days", self.keep_results_in_days),
+            ("keep_important_results_in_days", self.keep_important_results_in_days),
+        ];
+        for (k, v) in nums.iter() {
+            if let Some(v) = v {
+                params.push((k.to_string(), v.to_string(), false));
+            }
+        }
+        params
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Job {
+    pub id: i32,
+    pub name: String,
+    #[serde(default)]
+    pub test: String,
+    pub state: String,
+    pub result: String,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub group_id: Option<i32>,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub clone_id: Option<i32>,
+    #[serde(default)]
+    pub t_started: Option<String>,
+    #[serde(default)]
+    pub t_finished: Option<String>,
+    #[serde(default)]
+    pub settings: BTreeMap<String, String>,
+}
+
+/// Jobs for the tests of the other modules
+#[cfg(test)]
+impl Job {
+    /// A passed job of `test` on opensuse-Tumbleweed-DVD-x86_64@64bit
+    pub(crate) fn fake(id: i32, test: &str) -> Job {
+        let settings = [("DISTRI", "opensuse"), ("VERSION", "Tumbleweed"), ("FLAVOR", "DVD"),
+                        ("ARCH", "x86_64"), ("MACHINE", "64bit"), ("TEST", test)];
+        Job {
+            id,
+            name: format!("{}-{}", test, id),
+            test: test.to_string(),
+            state: "done".to_string(),
+            result: "passed".to_string(),
+            priority: 50,
+            group_id: None,
+            group: None,
+            clone_id: None,
+            t_started: None,
+            t_finished: None,
+            settings: settings.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
+
+    /// Set the result, a result of "none" meaning the job is still running
+    pub(crate) fn with_result(mut self, result: &str) -> Job {
+        self.state = if result == "none" { "running" } else { "done" }.to_string();
+        self.result = result.to_string();
+        self
+    }
+
+    pub(crate) fn with_group(mut self, id: i32, name: &str) -> Job {
+        self.group_id = Some(id);
+        self.group = Some(name.to_string());
+        self
+    }
+
+    pub(crate) fn with_clone(mut self, clone_id: Option<i32>) -> Job {
+        self.clone_id = clone_id;
+        self
+    }
+
+    pub(crate) fn with_setting(mut self, key: &str, value: &str) -> Job {
+        self.settings.insert(key.to_string(), value.to_string());
+        self
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JobResponse {
+    pub job: Job,
+}
+
+/// One step of a test module, e.g. a screenshot or a command's output
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Step {
+    /// ok, fail, softfail or unk
+    #[serde(default)]
+    pub result: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub needle: Option<String>,
+    #[serde(default)]
+    pub text_data: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TestModule {
+    pub name: String,
+    #[serde(default)]
+    pub category: String,
+    pub result: String,
+    #[serde(default)]
+    pub details: Vec<Step>,
+}
+
+/// A job with the results of its test modules
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobDetails {
+    #[serde(flatten)]
+    pub job: Job,
+    #[serde(default)]
+    pub testresults: Vec<TestModule>,
+}
+
+#[derive(Deserialize)]
+pub struct JobDetailsResponse {
+    pub job: JobDetails,
+}
+
+#[derive(Deserialize)]
+pub struct Jobs {
+    pub jobs: Vec<Job>,
+}
+
+#[derive(Deserialize)]
+pub struct JobIds {
+    pub jobs: Vec<i32>,
+}
+
+#[derive(Clone, Default)]
 pub struct OpenQA {
     ua: UserAgent,
+    ids: Arc<Mutex<Option<Arc<IdMap>>>>,
+    cache: Option<Arc<Cache>>,
+}
+
+type Params = Vec<(String, String, bool)>;
+
+/// The lists which change rarely enough to cache
+const CACHED: &[&str] = &["test_suites", "machines", "products", "job_templates", "job_groups"];
+
+/// The first part of an API path, e.g. "jobs" for "jobs/1/comments"
+fn table_of(url: &str) -> &str {
+    url.split(['/', '?']).next().unwrap_or_default()
+}
+
+/// Name a template's group, product, machine and test suite for `POST job_templates`
+fn template_params(template: &JobTemplateInfo, prio_only: bool) -> Params {
+    let p = &template.product;
+    let prio = template.prio.to_string();
+    let mut pairs = vec![("group_name", template.group_name.as_str()),
+                         ("machine_name", &template.machine.name),
+                         ("test_suite_name", &template.test_suite.name),
+                         ("arch", &p.arch),
+                         ("distri", &p.distri),
+                         ("flavor", &p.flavor),
+                         ("version", &p.version),
+                         ("prio", &prio)];
+    if prio_only {
+        pairs.push(("prio_only", "1"));
+    }
+    params(&pairs, &Settings::new())
+}
+
+fn params(pairs: &[(&str, &str)], settings: &Settings) -> Params {
+    let mut params: Params = pairs.iter()
+        .map(|(k, v)| (k.to_string(), v.to_string(), false))
+        .collect();
+    for s in settings {
+        params.push((s.key.clone(), s.value.clone(), true));
+    }
+    params
+}
+
+fn parse_body<T: DeserializeOwned>(body: &Chunk) -> Result<T, Error> {
+    serde_json::from_slice(body)
+        .map_err(|e| if let Ok(b) = String::from_utf8(body.to_vec()) {
+                    format_err!("Deserializing response: {}, Message body: {}",
+                                e, b)
+                } else {
+                    format_err!("Deserializing response: {}", e)
+                })
 }
 
 impl OpenQA {
@@ -135,6 +608,8 @@ impl OpenQA {
     {
         OpenQA {
             ua: UserAgent::new(host, key, secret),
+            ids: Arc::default(),
+            cache: None,
         }
     }
 
@@ -184,24 +659,140 @@ impl OpenQA {
 
         Ok(OpenQA {
             ua: UserAgent::new(format!("https://{}", host), key, secret),
+            ids: Arc::default(),
+            cache: None,
         })
     }
 
+    /// Send at most `limit.per_second` requests per second, on average
+    ///
+    /// Requests rejected with 429 Too Many Requests are always retried after
+    /// the delay given by the server; with a limit the client's other
+    /// requests wait for it too. Delays use tokio's timer, so the requests
+    /// must be run on a tokio runtime.
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> OpenQA {
+        self.ua.set_rate_limit(limit);
+        self
+    }
+
+    /// Send requests with `transport` instead of the default hyper client
+    pub fn with_transport<T: Transport + 'static>(mut self, transport: T) -> OpenQA {
+        self.ua.set_transport(transport);
+        self
+    }
+
+    /// Run `middleware` before each request is sent and after its response
+    pub fn with_middleware<M: Middleware + 'static>(mut self, middleware: M) -> OpenQA {
+        self.ua.add_middleware(middleware);
+        self
+    }
+
+    /// Record responses to, or replay them from, a fixture file
+    ///
+    /// Recording goes through the transport however the two are ordered.
+    /// When replaying nothing is sent, so the host, API key and rate limit
+    /// don't matter.
+    pub fn with_fixtures(mut self, fixtures: Fixtures) -> OpenQA {
+        self.ua.set_fixtures(fixtures);
+        self
+    }
+
+    /// Serve GET requests for test suites, machines, products, job templates
+    /// and job groups from `cache` when possible
+    ///
+    /// Jobs, comments, bugs and the like change too often and are always
+    /// fetched. Writes made through this client invalidate the affected
+    /// entries once the server has answered.
+    pub fn with_cache(mut self, cache: Cache) -> OpenQA {
+        self.cache = Some(Arc::new(cache));
+        self
+    }
+
+    /// Drop cached responses for the API path `prefix`, e.g. "test_suites"
+    pub fn invalidate(&self, prefix: &str) {
+        if let Some(ref c) = self.cache {
+            c.invalidate(&self.ua.url(""), prefix);
+        }
+    }
+
+    /// Returns a function which invalidates whatever a write to `url` may
+    /// have changed, to be called once the write is done
+    fn wrote(&self, url: &str) -> impl FnOnce() + Send {
+        let table = table_of(url).to_string();
+        let (cache, base, ids) = (self.cache.clone(), self.ua.url(""), self.ids.clone());
+
+        move || {
+            let invalidate = |prefix: &str| {
+                if let Some(ref c) = cache {
+                    c.invalidate(&base, prefix);
+                }
+            };
+
+            invalidate(&table);
+            if ["machines", "products", "test_suites", "job_groups"].contains(&table.as_str()) {
+                invalidate("job_templates");
+                *ids.lock().unwrap() = None;
+            }
+        }
+    }
+
+    fn get_uri<T>(&self, uri: Uri, url: &str) -> impl Future<Item=T, Error=Error>
+    where
+        T: DeserializeOwned,
+    {
+        let cache = self.cache.clone().filter(|_| CACHED.contains(&table_of(url)));
+        if let Some(body) = cache.as_ref().and_then(|c| c.get(&uri)) {
+            debug!("GET {} (cached)", uri);
+            return future::Either::A(future::result(parse_body(&Chunk::from(body))));
+        }
+
+        future::Either::B(self.ua.get(uri.clone()).and_then(move |body: Chunk| {
+            let res = parse_body(&body);
+            if let (Ok(_), Some(c)) = (&res, cache) {
+                c.put(&uri, &body);
+            }
+            future::result(res)
+        }))
+    }
+
     pub fn get<U, T>(&self, url: U) -> impl Future<Item=T, Error=Error>
     where
         U: AsRef<str>,
         T: DeserializeOwned,
     {
-        self.ua.get(self.ua.url(url.as_ref())).and_then(|body: Chunk| {
-            let res = serde_json::from_slice(&body)
-                .map_err(|e| if let Ok(b) = String::from_utf8(body.to_vec()) {
-                            format_err!("Deserializing response: {}, Message body: {}",
-                                        e, b)
-                        } else {
-                            format_err!("Deserializing response: {}", e)
-                        });
-            future::result(res)
-        })
+        self.get_uri(self.ua.url(url.as_ref()), url.as_ref())
+    }
+
+    /// Like `get`, but with (percent encoded) query parameters
+    pub fn get_query<U, T, K, V, P>(&self, url: U, pairs: P) -> impl Future<Item=T, Error=Error>
+    where
+        U: AsRef<str>,
+        T: DeserializeOwned,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+        P: AsRef<[(K, V, bool)]>,
+    {
+        self.get_uri(self.ua.url_query(url.as_ref(), pairs), url.as_ref())
+    }
+
+    /// Stream the items of a list, requesting `page_size` of them at a time
+    ///
+    /// The `next` links sent by openQA are followed until the last page.
+    /// The items are taken from `field` of each page, e.g. `Some("jobs")`, or
+    /// from the page itself if it is a list. Paged requests bypass the cache.
+    pub fn get_paged<U, T>(&self,
+                           url: U,
+                           field: Option<&str>,
+                           query: &[(&str, &str)],
+                           page_size: u32) -> impl Stream<Item=T, Error=Error>
+    where
+        U: AsRef<str>,
+        T: DeserializeOwned,
+    {
+        let mut pairs = params(query, &Settings::new());
+        pairs.push(("limit".to_string(), page_size.to_string(), false));
+
+        page::pages(self.ua.clone(), self.ua.url_query(url.as_ref(), pairs), field)
     }
 
     pub fn get_test_suites(&self) -> impl Future<Item=TestSuites, Error=Error>
@@ -224,6 +815,72 @@ impl OpenQA {
         self.get("job_templates")
     }
 
+    /// Send any request, returning the raw response body
+    ///
+    /// `path` is relative to the API base, e.g. "jobs/1/restart". `params`
+    /// are sent in the query string and `body`, if any, as JSON. The request
+    /// is signed whatever the method and bypasses the cache. The response
+    /// body is returned whatever its status.
+    pub fn request_bytes<K, V, P>(&self,
+                                  method: http::Method,
+                                  path: &str,
+                                  params: P,
+                                  body: Option<&Value>) -> impl Future<Item=Chunk, Error=Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+        P: AsRef<[(K, V, bool)]>,
+    {
+        let body = body.map(|b| serde_json::to_vec(b).map(Bytes::from));
+        let body = match body {
+            Some(Err(e)) => return future::Either::A(future::err(Error::from(e))),
+            Some(Ok(b)) => Some(b),
+            None => None,
+        };
+        let wrote = if method != http::Method::GET { Some(self.wrote(path)) } else { None };
+
+        let uri = self.ua.url_query(path, params);
+        debug!("{} {}", method, uri);
+        future::Either::B(self.ua.request(method, uri, body).map(move |(_, body)| {
+            if let Some(wrote) = wrote {
+                wrote();
+            }
+            body
+        }))
+    }
+
+    /// Like `request_bytes`, but parse the response as JSON into `T`, which
+    /// may be `serde_json::Value`
+    pub fn request<T, K, V, P>(&self,
+                               method: http::Method,
+                               path: &str,
+                               params: P,
+                               body: Option<&Value>) -> impl Future<Item=T, Error=Error>
+    where
+        T: DeserializeOwned,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+        P: AsRef<[(K, V, bool)]>,
+    {
+        self.request_bytes(method, path, params, body).and_then(|body: Chunk| {
+            future::result(parse_body(&body))
+        })
+    }
+
+    /// Run many requests, e.g. from this client's `new_job_template` or
+    /// `upd_test_suite`, with at most `limit` in flight at once
+    ///
+    /// The returned report holds the outcome of each request in order. The
+    /// requests still wait for the client's rate limit. See `bulk::run`.
+    pub fn bulk<I, F, T>(&self, limit: usize, on_error: OnError, requests: I)
+                         -> impl Future<Item=Report<T>, Error=Error>
+    where
+        I: IntoIterator<Item=F>,
+        F: future::IntoFuture<Item=T, Error=Error>,
+    {
+        bulk::run(limit, on_error, requests)
+    }
+
     pub fn post<U, T, K, V, P>(&self, url: U, pairs: P) -> impl Future<Item=T, Error=Error>
     where
         U: AsRef<str>,
@@ -232,32 +889,114 @@ impl OpenQA {
         V: AsRef<[u8]>,
         P: AsRef<[(K, V, bool)]>,
     {
+        let wrote = self.wrote(url.as_ref());
         self.ua.post(self.ua.url_query(url.as_ref(), pairs)).and_then(|body: Chunk| {
-            let res = serde_json::from_slice(&body)
-                .map_err(|e| if let Ok(b) = String::from_utf8(body.to_vec()) {
-                            format_err!("Deserializing response: {}, Message body: {}",
-                                        e, b)
-                        } else {
-                            format_err!("Deserializing response: {}", e)
-                        });
-            future::result(res)
+            wrote();
+            future::result(parse_body(&body))
         })
     }
 
-    pub fn upd_test_suite<'a>(&self, test: &'a TestSuite)
-                              -> impl Future<Item=UpdateResult, Error=Error> + 'a
+    pub fn put<U, T, K, V, P>(&self, url: U, pairs: P) -> impl Future<Item=T, Error=Error>
+    where
+        U: AsRef<str>,
+        T: DeserializeOwned,
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+        P: AsRef<[(K, V, bool)]>,
     {
-        let mut params: Vec<(&str, &str, bool)> = vec![
-            ("name", &test.name, false),
-            ("description", &test.description, false)
-        ];
-        for s in &test.settings {
-            params.push((&s.key, &s.value, true));
-        }
+        let wrote = self.wrote(url.as_ref());
+        self.ua.put(self.ua.url_query(url.as_ref(), pairs)).and_then(|body: Chunk| {
+            wrote();
+            future::result(parse_body(&body))
+        })
+    }
+
+    pub fn delete<U, T>(&self, url: U) -> impl Future<Item=T, Error=Error>
+    where
+        U: AsRef<str>,
+        T: DeserializeOwned,
+    {
+        let wrote = self.wrote(url.as_ref());
+        self.ua.delete(self.ua.url(url.as_ref())).and_then(|body: Chunk| {
+            wrote();
+            future::result(parse_body(&body))
+        })
+    }
+
+    pub fn upd_test_suite(&self, test: &TestSuite)
+                          -> impl Future<Item=UpdateResult, Error=Error>
+    {
+        let params = params(&[("name", &test.name), ("description", &test.description)],
+                            &test.settings);
 
         self.post(format!("test_suites/{}", test.id), params)
     }
 
+    /// Create a test suite, `test.id` is ignored
+    pub fn new_test_suite(&self, test: &TestSuite)
+                          -> impl Future<Item=CreateResult, Error=Error>
+    {
+        let params = params(&[("name", &test.name), ("description", &test.description)],
+                            &test.settings);
+
+        self.post("test_suites", params)
+    }
+
+    pub fn del_test_suite(&self, id: i32) -> impl Future<Item=UpdateResult, Error=Error>
+    {
+        self.delete(format!("test_suites/{}", id))
+    }
+
+    /// Create a machine, `machine.id` is ignored
+    pub fn new_machine(&self, machine: &Machine)
+                       -> impl Future<Item=CreateResult, Error=Error>
+    {
+        let params = params(&[("name", &machine.name), ("backend", &machine.backend)],
+                            &machine.settings);
+
+        self.post("machines", params)
+    }
+
+    pub fn upd_machine(&self, machine: &Machine)
+                       -> impl Future<Item=UpdateResult, Error=Error>
+    {
+        let params = params(&[("name", &machine.name), ("backend", &machine.backend)],
+                            &machine.settings);
+
+        self.post(format!("machines/{}", machine.id), params)
+    }
+
+    pub fn del_machine(&self, id: i32) -> impl Future<Item=UpdateResult, Error=Error>
+    {
+        self.delete(format!("machines/{}", id))
+    }
+
+    fn product_params(product: &Product) -> Params {
+        params(&[("arch", &product.arch),
+                 ("distri", &product.distri),
+                 ("flavor", &product.flavor),
+                 ("version", &product.version)],
+               &product.settings)
+    }
+
+    /// Create a product, `product.id` is ignored
+    pub fn new_product(&self, product: &Product)
+                       -> impl Future<Item=CreateResult, Error=Error>
+    {
+        self.post("products", OpenQA::product_params(product))
+    }
+
+    pub fn upd_product(&self, product: &Product)
+                       -> impl Future<Item=UpdateResult, Error=Error>
+    {
+        self.post(format!("products/{}", product.id), OpenQA::product_params(product))
+    }
+
+    pub fn del_product(&self, id: i32) -> impl Future<Item=UpdateResult, Error=Error>
+    {
+        self.delete(format!("products/{}", id))
+    }
+
     pub fn new_job_template(&self, template: &JobTemplate)
                             -> impl Future<Item=CreateResult, Error=Error>
     {
@@ -271,12 +1010,246 @@ impl OpenQA {
 
         self.post("job_templates", params)
     }
-}
 
-impl Default for OpenQA {
-    fn default() -> OpenQA {
-        OpenQA {
-            ua: UserAgent::default(),
+    /// Create a job template referring to its product, machine, test suite
+    /// and group by name instead of id
+    ///
+    /// The template's settings are not sent, as this API ignores them.
+    pub fn new_job_template_named(&self, template: &JobTemplateInfo)
+                                  -> impl Future<Item=CreateResult, Error=Error>
+    {
+        self.post("job_templates", template_params(template, false))
+    }
+
+    /// Change the priority of the template with the same group, product,
+    /// machine and test suite, keeping its id
+    pub fn upd_job_template_prio(&self, template: &JobTemplateInfo)
+                                 -> impl Future<Item=CreateResult, Error=Error>
+    {
+        self.post("job_templates", template_params(template, true))
+    }
+
+    pub fn del_job_template(&self, id: i32) -> impl Future<Item=UpdateResult, Error=Error>
+    {
+        self.delete(format!("job_templates/{}", id))
+    }
+
+    pub fn get_job_groups(&self) -> impl Future<Item=Vec<JobGroup>, Error=Error>
+    {
+        self.get("job_groups")
+    }
+
+    /// Create a job group, `group.id` is ignored
+    pub fn new_job_group(&self, group: &JobGroup) -> impl Future<Item=CreateResult, Error=Error>
+    {
+        self.post("job_groups", group.params())
+    }
+
+    /// Update a job group, on success the result contains its id
+    pub fn upd_job_group(&self, group: &JobGroup) -> impl Future<Item=CreateResult, Error=Error>
+    {
+        self.put(format!("job_groups/{}", group.id), group.params())
+    }
+
+    /// Name to id mappings, fetched on first use and then cached
+    ///
+    /// Writing to the test suites, machines, products or job groups through
+    /// this client clears the mappings. Call `forget_ids` if they have been
+    /// changed by something else.
+    pub fn ids(&self) -> impl Future<Item=Arc<IdMap>, Error=Error> + '_
+    {
+        if let Some(ids) = self.ids.lock().unwrap().clone() {
+            return future::Either::A(future::ok(ids));
         }
+
+        future::Either::B(
+            self.get_test_suites()
+                .join4(self.get_machines(), self.get_products(), self.get_job_groups())
+                .map(move |(t, m, p, g)| {
+                    let ids = Arc::new(IdMap::new(&t.test_suites, &m.machines,
+                                                  &p.products, &g));
+                    *self.ids.lock().unwrap() = Some(ids.clone());
+                    ids
+                })
+        )
+    }
+
+    pub fn forget_ids(&self) {
+        *self.ids.lock().unwrap() = None;
+    }
+
+    pub fn job_template_from_names(&self,
+                                   group: &str,
+                                   product: &ProductKey,
+                                   machine: &str,
+                                   test_suite: &str)
+                                   -> impl Future<Item=JobTemplate, Error=Error> + '_
+    {
+        let (group, product) = (group.to_string(), product.clone());
+        let (machine, test_suite) = (machine.to_string(), test_suite.to_string());
+
+        self.ids().and_then(move |ids| {
+            JobTemplate::from_names(&ids, &group, &product, &machine, &test_suite)
+        })
+    }
+
+    pub fn get_comments(&self, target: CommentTarget)
+                        -> impl Future<Item=Vec<Comment>, Error=Error>
+    {
+        self.get(target.path())
+    }
+
+    /// Add a comment, on success the result contains the new comment's id
+    pub fn new_comment<'a>(&self, target: CommentTarget, text: &'a str)
+                           -> impl Future<Item=CreateResult, Error=Error> + 'a
+    {
+        self.post(target.path(), [("text", text, false)])
+    }
+
+    /// Replace a comment's text, on success the result contains its id
+    pub fn upd_comment<'a>(&self, target: CommentTarget, comment_id: i32, text: &'a str)
+                           -> impl Future<Item=CreateResult, Error=Error> + 'a
+    {
+        self.put(format!("{}/{}", target.path(), comment_id), [("text", text, false)])
+    }
+
+    /// Delete a comment, on success the result contains its id
+    pub fn del_comment(&self, target: CommentTarget, comment_id: i32)
+                       -> impl Future<Item=CreateResult, Error=Error>
+    {
+        self.delete(format!("{}/{}", target.path(), comment_id))
+    }
+
+    pub fn get_bugs(&self, filter: BugFilter) -> impl Future<Item=Bugs, Error=Error>
+    {
+        self.get(match filter {
+            BugFilter::All => "bugs".to_string(),
+            BugFilter::Refreshable(None) => "bugs?refreshable=1".to_string(),
+            BugFilter::Refreshable(Some(secs)) => format!("bugs?refreshable=1&delta={}", secs),
+            BugFilter::CreatedWithin(secs) => format!("bugs?created_since={}", secs),
+        })
+    }
+
+    pub fn get_bug(&self, id: i32) -> impl Future<Item=Bug, Error=Error>
+    {
+        self.get(format!("bugs/{}", id))
+    }
+
+    /// Create a bug entry from `bug.bugid` and its status fields
+    pub fn new_bug(&self, bug: &Bug) -> impl Future<Item=CreateResult, Error=Error>
+    {
+        let mut params = bug.params();
+        params.push(("bugid", bug.bugid.clone(), false));
+
+        self.post("bugs", params)
+    }
+
+    /// Update the status fields of the bug with id `bug.id`
+    pub fn upd_bug(&self, bug: &Bug) -> impl Future<Item=CreateResult, Error=Error>
+    {
+        self.put(format!("bugs/{}", bug.id), bug.params())
+    }
+
+    pub fn del_bug(&self, id: i32) -> impl Future<Item=UpdateResult, Error=Error>
+    {
+        self.delete(format!("bugs/{}", id))
+    }
+
+    pub fn get_job(&self, id: i32) -> impl Future<Item=Job, Error=Error>
+    {
+        self.get(format!("jobs/{}", id)).map(|res: JobResponse| res.job)
+    }
+
+    pub fn get_job_details(&self, id: i32) -> impl Future<Item=JobDetails, Error=Error>
+    {
+        self.get(format!("jobs/{}/details", id)).map(|res: JobDetailsResponse| res.job)
+    }
+
+    pub fn get_jobs(&self, ids: &[i32]) -> impl Future<Item=Vec<Job>, Error=Error>
+    {
+        let params: Vec<_> = ids.iter()
+            .map(|id| ("ids", id.to_string(), false))
+            .collect();
+
+        self.get_query("jobs", params).map(|res: Jobs| res.jobs)
+    }
+
+    /// The latest job of each scenario in a group's build
+    pub fn get_build_jobs(&self, group_id: i32, build: &str)
+                          -> impl Future<Item=Vec<Job>, Error=Error>
+    {
+        let params = [("groupid", group_id.to_string(), false),
+                      ("build", build.to_string(), false),
+                      ("latest", "1".to_string(), false)];
+
+        self.get_query("jobs", params).map(|res: Jobs| res.jobs)
+    }
+
+    /// The last `limit` jobs of a scenario, newest first
+    ///
+    /// Jobs the server returns for another scenario are skipped, and further
+    /// pages are fetched until `limit` jobs match or there are no more.
+    pub fn get_scenario_history(&self, scenario: &Scenario, limit: u32)
+                                -> impl Future<Item=Vec<Job>, Error=Error>
+    {
+        let jobs = self.get_all_jobs(&scenario.query(), cmp::max(limit, 1));
+        let scenario = scenario.clone();
+
+        jobs.filter(move |j| Scenario::from(j) == scenario)
+            .take(u64::from(limit))
+            .collect()
+            .map(|mut jobs| {
+                jobs.sort_by_key(|j| cmp::Reverse(j.id));
+                jobs
+            })
+    }
+
+    /// Find recent jobs which have the setting `key` containing `value`
+    ///
+    /// The key may contain `*` wildcards. The value must match exactly or be
+    /// one of the items in a comma separated setting value. openQA limits
+    /// the search to the most recent jobs.
+    pub fn search_job_settings(&self, key: &str, value: &str)
+                               -> impl Future<Item=Vec<i32>, Error=Error>
+    {
+        let params = [("key", key.to_string(), false),
+                      ("list_value", value.to_string(), false)];
+
+        self.get_query("job_settings/jobs", params).map(|res: JobIds| res.jobs)
+    }
+
+    /// Fetch the jobs found by `search_job_settings`
+    pub fn get_jobs_with_setting(&self, key: &str, value: &str)
+                                 -> impl Future<Item=Vec<Job>, Error=Error> + '_
+    {
+        self.search_job_settings(key, value).and_then(move |ids| {
+            if ids.is_empty() {
+                future::Either::A(future::ok(Vec::new()))
+            } else {
+                future::Either::B(self.get_jobs(&ids))
+            }
+        })
+    }
+
+    /// Poll jobs until they are done or cancelled and return the final jobs
+    ///
+    /// Restarted jobs are replaced by their clones unless `opts` says not to.
+    /// The future holds its own handle on the client, so it can be spawned.
+    pub fn wait_for_jobs(&self, ids: &[i32], opts: WaitOptions)
+                         -> impl Future<Item=Vec<Job>, Error=Error> + Send
+    {
+        wait::wait_for_jobs(self.clone(), ids, opts)
+    }
+
+    /// Stream status updates of a running job from the live view handler
+    pub fn follow_job(&self, id: i32) -> impl Stream<Item=live::Update, Error=Error> {
+        live::follow(&self.ua, id)
+    }
+
+    /// Stream all jobs matching `query`, e.g. `[("groupid", "3")]`
+    pub fn get_all_jobs(&self, query: &[(&str, &str)], page_size: u32)
+                        -> impl Stream<Item=Job, Error=Error>
+    {
+        self.get_paged("jobs", Some("jobs"), query, page_size)
     }
 }
@@ -0,0 +1,361 @@
+//! Rules for checking and migrating lists of settings
+//!
+//! A `RuleSet` is run against the settings of a test suite (or machine,
+//! product...) and produces `Finding`s. Findings may carry a `Change` which
+//! fixes the problem, so a migration script becomes a rule set plus a loop
+//! which prints the resulting diff and posts the update.
+
+use std::fmt;
+
+use {Setting, Settings};
+
+/// Match `text` against a shell style pattern where `*` matches any
+/// sequence of characters and `?` matches any single character
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    Add(Setting),
+    Update { key: String, old: String, new: String },
+    Remove(Setting),
+}
+
+impl Change {
+    pub fn key(&self) -> &str {
+        match self {
+            Change::Add(s) | Change::Remove(s) => &s.key,
+            Change::Update { key, .. } => key,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Add(s) => write!(f, "+{}={}", s.key, s.value),
+            Change::Remove(s) => write!(f, "-{}={}", s.key, s.value),
+            Change::Update { key, old, new } => write!(f, "-{}={}\n+{}={}", key, old, key, new),
+        }
+    }
+}
+
+/// The changes needed to turn `old` into `new`
+pub fn diff(old: &Settings, new: &Settings) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    for o in old {
+        match new.get_setting(&o.key) {
+            None => changes.push(Change::Remove(o.clone())),
+            Some(n) if n.value != o.value => changes.push(Change::Update {
+                key: o.key.clone(),
+                old: o.value.clone(),
+                new: n.value.clone(),
+            }),
+            Some(_) => (),
+        }
+    }
+    for n in new {
+        if !old.contains(&n.key) {
+            changes.push(Change::Add(n.clone()));
+        }
+    }
+
+    changes
+}
+
+/// Apply changes in order. Adding an existing key replaces its value.
+pub fn apply(settings: &mut Settings, changes: &[Change]) {
+    for c in changes {
+        match c {
+            Change::Add(s) => { settings.set(s.key.as_str(), s.value.as_str()); },
+            Change::Update { key, new, .. } => { settings.set(key.as_str(), new.as_str()); },
+            Change::Remove(s) => { settings.remove(&s.key); },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Finding {
+    pub rule: String,
+    pub message: String,
+    /// A suggested fix, if the rule knows of one
+    pub change: Option<Change>,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.rule, self.message)
+    }
+}
+
+pub trait Rule {
+    fn name(&self) -> &str;
+    fn check(&self, settings: &Settings) -> Option<Finding>;
+}
+
+/// If `key` is set then `requires` must be set as well
+pub struct Requires {
+    pub key: String,
+    pub requires: String,
+}
+
+impl Requires {
+    pub fn new<K: Into<String>, R: Into<String>>(key: K, requires: R) -> Requires {
+        Requires { key: key.into(), requires: requires.into() }
+    }
+}
+
+impl Rule for Requires {
+    fn name(&self) -> &str {
+        "requires"
+    }
+
+    fn check(&self, settings: &Settings) -> Option<Finding> {
+        match (settings.get_setting(&self.key), settings.get_setting(&self.requires)) {
+            (Some(_), None) => Some(Finding {
+                rule: self.name().to_string(),
+                message: format!("{} is set, but {} is missing", self.key, self.requires),
+                change: None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// If `key` is set then its value must match the glob `pattern`
+pub struct Matches {
+    pub key: String,
+    pub pattern: String,
+}
+
+impl Matches {
+    pub fn new<K: Into<String>, P: Into<String>>(key: K, pattern: P) -> Matches {
+        Matches { key: key.into(), pattern: pattern.into() }
+    }
+}
+
+impl Rule for Matches {
+    fn name(&self) -> &str {
+        "matches"
+    }
+
+    fn check(&self, settings: &Settings) -> Option<Finding> {
+        match settings.get_setting(&self.key) {
+            Some(s) if !glob_match(&self.pattern, &s.value) => Some(Finding {
+                rule: self.name().to_string(),
+                message: format!("{}={} does not match '{}'", s.key, s.value, self.pattern),
+                change: None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Add `key` with a value computed from the settings in `from`
+///
+/// The rule only applies when `key` is missing and all of `from` are set. The
+/// function receives the values of `from` in order and may return `None` if
+/// nothing should be derived from them.
+pub struct Derive<F> {
+    pub name: String,
+    pub key: String,
+    pub from: Vec<String>,
+    pub derive: F,
+}
+
+impl<F> Derive<F>
+where
+    F: Fn(&[&str]) -> Option<String>,
+{
+    pub fn new<N, K>(name: N, key: K, from: &[&str], derive: F) -> Derive<F>
+    where
+        N: Into<String>,
+        K: Into<String>,
+    {
+        Derive {
+            name: name.into(),
+            key: key.into(),
+            from: from.iter().map(|s| s.to_string()).collect(),
+            derive,
+        }
+    }
+}
+
+impl<F> Rule for Derive<F>
+where
+    F: Fn(&[&str]) -> Option<String>,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn check(&self, settings: &Settings) -> Option<Finding> {
+        if settings.contains(&self.key) {
+            return None;
+        }
+
+        let values = self.from.iter()
+            .map(|k| settings.get(k))
+            .collect::<Option<Vec<_>>>()?;
+        let value = (self.derive)(&values)?;
+
+        Some(Finding {
+            rule: self.name.clone(),
+            message: format!("{} can be derived from {}", self.key, self.from.join(", ")),
+            change: Some(Change::Add(Setting { key: self.key.clone(), value })),
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct RuleSet {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl RuleSet {
+    pub fn new() -> RuleSet {
+        RuleSet::default()
+    }
+
+    pub fn with<R: Rule + 'static>(mut self, rule: R) -> RuleSet {
+        self.push(rule);
+        self
+    }
+
+    pub fn push<R: Rule + 'static>(&mut self, rule: R) {
+        self.rules.push(Box::new(rule));
+    }
+
+    /// Run every rule against the unmodified settings
+    pub fn check(&self, settings: &Settings) -> Vec<Finding> {
+        self.rules.iter().filter_map(|r| r.check(settings)).collect()
+    }
+
+    /// Run the rules in order, applying each suggested change before the
+    /// next rule is checked, so later rules see the earlier fixes
+    pub fn fix(&self, settings: &mut Settings) -> Vec<Finding> {
+        let mut findings = Vec::new();
+
+        for r in &self.rules {
+            if let Some(f) = r.check(settings) {
+                if let Some(ref c) = f.change {
+                    apply(settings, ::std::slice::from_ref(c));
+                }
+                findings.push(f);
+            }
+        }
+
+        findings
+    }
+}
+
+fn uefi_vars_name(hdd: &str) -> String {
+    format!("{}-uefi-vars.qcow2", hdd.trim_end_matches(".qcow2"))
+}
+
+/// Add `PUBLISH_PFLASH_VARS` and `UEFI_PFLASH_VARS` alongside the qcow2 images
+/// published by, or booted from, a parent test
+pub fn pflash_vars() -> RuleSet {
+    RuleSet::new()
+        .with(Derive::new("publish-pflash-vars", "PUBLISH_PFLASH_VARS",
+                          &["PUBLISH_HDD_1"], |v| {
+            if v[0].ends_with(".qcow2") { Some(uefi_vars_name(v[0])) } else { None }
+        }))
+        .with(Derive::new("uefi-pflash-vars", "UEFI_PFLASH_VARS",
+                          &["HDD_1", "START_AFTER_TEST"], |v| {
+            if v[0].ends_with(".qcow2") { Some(uefi_vars_name(v[0])) } else { None }
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pairs: &[(&str, &str)]) -> Settings {
+        pairs.iter().cloned().collect()
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("*.qcow2", "sle-15.qcow2"));
+        assert!(glob_match("sle-??-*", "sle-15-SP1"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("*.qcow2", "sle-15.iso"));
+        assert!(!glob_match("sle-?", "sle-15"));
+    }
+
+    #[test]
+    fn diff_and_apply() {
+        let old = settings(&[("A", "1"), ("B", "2"), ("C", "3")]);
+        let new = settings(&[("A", "1"), ("B", "x"), ("D", "4")]);
+        let changes = diff(&old, &new);
+
+        assert_eq!(3, changes.len());
+        assert_eq!("-B=2\n+B=x", changes[0].to_string());
+        assert_eq!("-C=3", changes[1].to_string());
+        assert_eq!("+D=4", changes[2].to_string());
+
+        let mut patched = old.clone();
+        apply(&mut patched, &changes);
+        assert_eq!(new, patched);
+    }
+
+    #[test]
+    fn rules() {
+        let rules = RuleSet::new()
+            .with(Requires::new("PUBLISH_HDD_1", "STORE_HDD_1"))
+            .with(Matches::new("HDD_1", "*.qcow2"));
+        let sets = settings(&[("PUBLISH_HDD_1", "a.qcow2"), ("HDD_1", "b.iso")]);
+        let found = rules.check(&sets);
+
+        assert_eq!(2, found.len());
+        assert_eq!("requires", found[0].rule);
+        assert_eq!("matches", found[1].rule);
+        assert!(found.iter().all(|f| f.change.is_none()));
+    }
+
+    #[test]
+    fn pflash() {
+        let rules = pflash_vars();
+        let orig = settings(&[("PUBLISH_HDD_1", "sle.qcow2"),
+                              ("HDD_1", "base.qcow2"),
+                              ("START_AFTER_TEST", "create_hdd")]);
+        let mut sets = orig.clone();
+
+        assert_eq!(2, rules.fix(&mut sets).len());
+        assert_eq!(vec![
+            Change::Add(Setting { key: "PUBLISH_PFLASH_VARS".to_string(),
+                                  value: "sle-uefi-vars.qcow2".to_string() }),
+            Change::Add(Setting { key: "UEFI_PFLASH_VARS".to_string(),
+                                  value: "base-uefi-vars.qcow2".to_string() }),
+        ], diff(&orig, &sets));
+        assert!(rules.fix(&mut sets).is_empty());
+
+        let mut sets = settings(&[("HDD_1", "base.qcow2")]);
+        assert!(rules.fix(&mut sets).is_empty());
+    }
+}
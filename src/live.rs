@@ -0,0 +1,297 @@
+//! Follow a running job through openQA's live view handler
+//!
+//! The live view handler proxies the os-autoinst command server of a
+//! running job over a websocket. Its status route is read only and sends
+//! JSON messages like `{"type": "info", "what": "cmdsrv-message", "data":
+//! {...}}` whenever the current module, step or developer mode state
+//! changes.
+//!
+//! The upgrade request goes through `UserAgent`, then tungstenite speaks
+//! the websocket protocol over the upgraded connection.
+
+use std::io::{self, Read, Write};
+
+use base64;
+use bytes::Bytes;
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use futures::{future, Async, Future, Poll, Stream};
+use http::header::{HeaderMap, HeaderValue};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde_json::{self, Value};
+use tungstenite::error::Error as WsError;
+use tungstenite::protocol::{Role, WebSocket, WebSocketConfig};
+use tungstenite::Message as WsMessage;
+use failure::Error;
+
+use UserAgent;
+
+/// Appended to the client's key to make the server's accept key
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Larger messages, however many frames they are split over, are refused
+const MAX_MESSAGE: usize = 16 << 20;
+
+/// The command server's state, only the fields which changed are set
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    /// The test module being run
+    #[serde(default)]
+    pub running: Option<String>,
+    #[serde(default)]
+    pub current_test_full_name: Option<String>,
+    /// The test API function being run, e.g. `assert_screen`
+    #[serde(default)]
+    pub current_api_function: Option<String>,
+    /// Why the test is paused, false or 0 when it isn't
+    #[serde(default)]
+    pub test_execution_paused: Option<Value>,
+    /// The module the developer asked to pause at
+    #[serde(default)]
+    pub pause_test_name: Option<String>,
+    #[serde(default)]
+    pub pause_on_screen_mismatch: Option<Value>,
+    #[serde(default)]
+    pub pause_on_next_command: Option<Value>,
+}
+
+impl Status {
+    /// Whether this says the test is paused
+    pub fn is_paused(&self) -> bool {
+        match self.test_execution_paused {
+            Some(Value::Bool(b)) => b,
+            Some(Value::Number(ref n)) => n.as_f64() != Some(0.0),
+            Some(Value::String(ref s)) => !s.is_empty() && s != "0",
+            _ => false,
+        }
+    }
+}
+
+/// A message from the live view handler
+#[derive(Debug, Clone, PartialEq)]
+pub enum Update {
+    /// The command server's state changed
+    Status(Status),
+    /// Any other information, e.g. that a developer session started
+    Info { what: String, data: Value },
+    /// The handler couldn't reach the command server or similar
+    Error { what: String, data: Value },
+}
+
+#[derive(Deserialize)]
+struct Message {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    what: String,
+    #[serde(default)]
+    data: Value,
+}
+
+impl Update {
+    pub fn parse(text: &[u8]) -> Result<Update, Error> {
+        let msg: Message = serde_json::from_slice(text)?;
+
+        Ok(match msg.kind.as_str() {
+            "error" => Update::Error { what: msg.what, data: msg.data },
+            _ if msg.what == "cmdsrv-message" => {
+                Update::Status(serde_json::from_value(msg.data)?)
+            },
+            _ => Update::Info { what: msg.what, data: msg.data },
+        })
+    }
+}
+
+/// Stream the status of job `id` until it finishes or the connection closes
+///
+/// The upgrade request is signed with the user agent's API key and sent
+/// through its transport, which must support `Transport::upgrade`.
+pub fn follow(ua: &UserAgent, id: i32) -> impl Stream<Item=Update, Error=Error> {
+    let url = ua.host_url(&format!("liveviewhandler/tests/{}/developer/ws-proxy/status", id));
+    let ua = ua.clone();
+
+    future::result(websocket_key()).and_then(move |key| {
+        let mut hdrs = HeaderMap::new();
+
+        hdrs.insert("Connection", HeaderValue::from_static("Upgrade"));
+        hdrs.insert("Upgrade", HeaderValue::from_static("websocket"));
+        hdrs.insert("Sec-WebSocket-Version", HeaderValue::from_static("13"));
+        hdrs.insert("Sec-WebSocket-Key", HeaderValue::from_str(&key).unwrap());
+
+        ua.upgrade(url, hdrs).and_then(move |(parts, io)| {
+            let accept = parts.headers.get("Sec-WebSocket-Accept")
+                .map(|v| v.as_bytes().to_vec())
+                .unwrap_or_default();
+            if accept != accept_key(&key).into_bytes() {
+                bail!("Live view handler sent a bad Sec-WebSocket-Accept");
+            }
+            Ok(Messages::new(io, MAX_MESSAGE))
+        })
+    }).flatten_stream().and_then(|text: Bytes| Update::parse(&text))
+}
+
+/// A random key for the opening handshake, from the OS's CSPRNG
+fn websocket_key() -> Result<String, Error> {
+    let mut nonce = [0; 16];
+    OsRng.try_fill_bytes(&mut nonce)
+        .map_err(|e| format_err!("Generating a websocket key: {}", e))?;
+    Ok(base64::encode(&nonce))
+}
+
+fn accept_key(key: &str) -> String {
+    let mut sha = Sha1::new();
+    let mut hash = [0; 20];
+
+    sha.input_str(key);
+    sha.input_str(GUID);
+    sha.result(&mut hash);
+    base64::encode(&hash)
+}
+
+/// The text and binary messages received on a websocket connection
+///
+/// tungstenite answers pings and the server's close frame by itself.
+struct Messages<T> {
+    ws: WebSocket<T>,
+}
+
+impl<T: Read + Write> Messages<T> {
+    fn new(io: T, max_size: usize) -> Messages<T> {
+        let config = WebSocketConfig {
+            max_message_size: Some(max_size),
+            max_frame_size: Some(max_size),
+            ..WebSocketConfig::default()
+        };
+        Messages { ws: WebSocket::from_raw_socket(io, Role::Client, Some(config)) }
+    }
+}
+
+impl<T: Read + Write> Stream for Messages<T> {
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, Error> {
+        loop {
+            match self.ws.read_message() {
+                Ok(WsMessage::Text(text)) => return Ok(Async::Ready(Some(text.into()))),
+                Ok(WsMessage::Binary(data)) => return Ok(Async::Ready(Some(data.into()))),
+                Ok(_) => (),
+                Err(WsError::ConnectionClosed) | Err(WsError::AlreadyClosed) => {
+                    return Ok(Async::Ready(None));
+                },
+                Err(WsError::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady);
+                },
+                Err(e) => bail!("Websocket: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use tungstenite::protocol::frame::coding::CloseCode;
+
+    /// Reads what the server sent and keeps what the client writes
+    struct Pipe {
+        from_server: Cursor<Vec<u8>>,
+        to_server: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.from_server.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.to_server.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const CONTINUATION: u8 = 0x0;
+    const TEXT: u8 = 0x1;
+    const CLOSE: u8 = 0x8;
+    const PING: u8 = 0x9;
+
+    fn frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut f = vec![if fin { 0x80 } else { 0 } | opcode];
+        if payload.len() < 126 {
+            f.push(payload.len() as u8);
+        } else {
+            f.push(126);
+            f.extend_from_slice(&[(payload.len() >> 8) as u8, payload.len() as u8]);
+        }
+        f.extend_from_slice(payload);
+        f
+    }
+
+    fn pipe(input: Vec<u8>) -> Pipe {
+        Pipe { from_server: Cursor::new(input), to_server: Vec::new() }
+    }
+
+    #[test]
+    fn handshake_key() {
+        // From RFC 6455
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+        assert_eq!(24, websocket_key().unwrap().len());
+    }
+
+    #[test]
+    fn updates() {
+        let status = br#"{"type":"info","what":"cmdsrv-message","data":
+            {"running":"boot","current_api_function":"assert_screen","test_execution_paused":"reason"}}"#;
+        let long = format!(r#"{{"type":"info","what":"note","data":"{}"}}"#, "x".repeat(300));
+        let mut input = frame(true, TEXT, status);
+        input.extend(frame(true, PING, b"hi"));
+        input.extend(frame(false, TEXT, &long.as_bytes()[..100]));
+        input.extend(frame(true, CONTINUATION, &long.as_bytes()[100..]));
+        input.extend(frame(true, TEXT, br#"{"type":"error","what":"gone"}"#));
+        input.extend(frame(true, CLOSE, &[0x03, 0xe8]));
+
+        let mut pipe = pipe(input);
+        let updates: Vec<Update> = Messages::new(&mut pipe, MAX_MESSAGE)
+            .and_then(|text| Update::parse(&text))
+            .collect().wait().unwrap();
+
+        assert_eq!(3, updates.len());
+        match updates[0] {
+            Update::Status(ref s) => {
+                assert_eq!(Some("boot"), s.running.as_deref());
+                assert_eq!(Some("assert_screen"), s.current_api_function.as_deref());
+                assert!(s.is_paused());
+            },
+            ref u => panic!("Expected status, got {:?}", u),
+        }
+        assert_eq!(Update::Info { what: "note".to_string(), data: Value::String("x".repeat(300)) },
+                   updates[1]);
+        assert_eq!(Update::Error { what: "gone".to_string(), data: Value::Null }, updates[2]);
+
+        // The client answers the ping and echoes the close
+        let mut server = WebSocket::from_raw_socket(Cursor::new(pipe.to_server), Role::Server, None);
+        assert_eq!(WsMessage::Pong(b"hi".to_vec()), server.read_message().unwrap());
+        match server.read_message().unwrap() {
+            WsMessage::Close(Some(c)) => assert_eq!(CloseCode::Normal, c.code),
+            m => panic!("Expected close, got {:?}", m),
+        }
+    }
+
+    #[test]
+    fn message_limit() {
+        // Each frame is small but the message is not
+        let mut input = frame(false, TEXT, &[b'x'; 100]);
+        input.extend(frame(false, CONTINUATION, &[b'x'; 100]));
+        input.extend(frame(true, CONTINUATION, &[b'x'; 100]));
+
+        let mut pipe = pipe(input);
+        let err = Messages::new(&mut pipe, 250).collect().wait().unwrap_err();
+        assert!(err.to_string().starts_with("Websocket: "), "{}", err);
+    }
+}
@@ -0,0 +1,199 @@
+//! Hooks which run around every request `UserAgent` sends
+//!
+//! Each attempt, including those retried after 429 Too Many Requests, is
+//! passed to `before_send` once it is signed and to `after_response` when
+//! the transport is done with it. `UserAgent` also wraps each request in a
+//! `tracing` span named `openqa_request` with the method, path, status,
+//! latency and retry count. The span is entered whenever the request's
+//! future is polled, so events from the hooks belong to it too.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::{self, Method, Uri};
+use http::header::HeaderMap;
+use failure::Error;
+
+use transport::Response;
+
+pub(crate) const REDACTED: &str = "<redacted>";
+const SECRET_HEADERS: &[&str] = &["x-api-key", "x-api-hash", "x-api-microtime",
+                                  "cookie", "set-cookie", "authorization"];
+
+/// The headers as strings with the API key, hash, time and cookies hidden
+pub fn redacted(hdrs: &HeaderMap) -> BTreeMap<String, String> {
+    hdrs.iter().map(|(k, v)| {
+        let v = if SECRET_HEADERS.contains(&k.as_str()) {
+            REDACTED.to_string()
+        } else {
+            String::from_utf8_lossy(v.as_bytes()).into_owned()
+        };
+        (k.as_str().to_string(), v)
+    }).collect()
+}
+
+/// An attempt at sending a request
+pub struct Attempt<'a> {
+    pub method: &'a Method,
+    pub uri: &'a Uri,
+    /// How many times the request was retried before this attempt
+    pub retries: u32,
+    /// Time from sending the request to reading the whole response
+    pub latency: Duration,
+}
+
+pub trait Middleware: Send + Sync {
+    /// Inspect or change a request before it is sent
+    ///
+    /// The request is already signed, so changing the path or query will
+    /// invalidate the signature.
+    fn before_send(&self, _req: &mut http::Request<Bytes>) {}
+
+    /// Inspect the response, or the error, of an attempt
+    fn after_response(&self, _attempt: &Attempt, _res: Result<&Response, &Error>) {}
+}
+
+impl<T: Middleware + ?Sized> Middleware for Arc<T> {
+    fn before_send(&self, req: &mut http::Request<Bytes>) {
+        (**self).before_send(req)
+    }
+
+    fn after_response(&self, attempt: &Attempt, res: Result<&Response, &Error>) {
+        (**self).after_response(attempt, res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+    use futures::{future, Future};
+    use tracing::{self, span, Event, Metadata, Subscriber};
+    use tracing::field::{Field, Visit};
+    use transport::{ResponseFuture, Transport};
+    use UserAgent;
+
+    struct Echo;
+
+    impl Transport for Echo {
+        fn send(&self, req: http::Request<Bytes>) -> ResponseFuture {
+            let (mut parts, _) = http::Response::new(()).into_parts();
+            parts.headers = req.headers().clone();
+            Box::new(future::ok((parts, "{}".into())))
+        }
+    }
+
+    /// The path, retries and response headers of an attempt
+    type Seen = (String, u32, BTreeMap<String, String>);
+
+    #[derive(Default)]
+    struct Tag {
+        seen: Mutex<Vec<Seen>>,
+    }
+
+    impl Middleware for Tag {
+        fn before_send(&self, req: &mut http::Request<Bytes>) {
+            req.headers_mut().insert("x-request-id", "42".parse().unwrap());
+        }
+
+        fn after_response(&self, attempt: &Attempt, res: Result<&Response, &Error>) {
+            let hdrs = redacted(&res.unwrap().0.headers);
+            self.seen.lock().unwrap().push((attempt.uri.path().to_string(), attempt.retries, hdrs));
+        }
+    }
+
+    #[test]
+    fn hooks() {
+        let tag = Arc::new(Tag::default());
+        let mut ua = UserAgent::new("http://echo", "key", "secret");
+        ua.set_transport(Echo);
+        ua.add_middleware(tag.clone());
+
+        ua.post(ua.url("machines")).wait().unwrap();
+
+        let seen = tag.seen.lock().unwrap();
+        assert_eq!(1, seen.len());
+        let (ref path, retries, ref hdrs) = seen[0];
+        assert_eq!("/api/v1/machines", path);
+        assert_eq!(0, retries);
+        assert_eq!("42", hdrs["x-request-id"]);
+        assert_eq!(REDACTED, hdrs["x-api-key"]);
+        assert_eq!(REDACTED, hdrs["x-api-hash"]);
+    }
+
+    /// Counts the events sent inside and outside spans and the fields recorded on them
+    #[derive(Default)]
+    struct Spans {
+        depth: AtomicUsize,
+        inside: AtomicUsize,
+        outside: AtomicUsize,
+        recorded: Mutex<Vec<String>>,
+    }
+
+    struct Names<'a>(&'a mut Vec<String>);
+
+    impl<'a> Visit for Names<'a> {
+        fn record_debug(&mut self, field: &Field, _: &dyn fmt::Debug) {
+            self.0.push(field.name().to_string());
+        }
+    }
+
+    impl Subscriber for Spans {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &span::Attributes) -> span::Id {
+            span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &span::Id, values: &span::Record) {
+            values.record(&mut Names(&mut self.recorded.lock().unwrap()));
+        }
+
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, _: &Event) {
+            let count = if self.depth.load(SeqCst) > 0 { &self.inside } else { &self.outside };
+            count.fetch_add(1, SeqCst);
+        }
+
+        fn enter(&self, _: &span::Id) {
+            self.depth.fetch_add(1, SeqCst);
+        }
+
+        fn exit(&self, _: &span::Id) {
+            self.depth.fetch_sub(1, SeqCst);
+        }
+    }
+
+    struct Log;
+
+    impl Middleware for Log {
+        fn before_send(&self, _: &mut http::Request<Bytes>) {
+            tracing::info!("before send");
+        }
+    }
+
+    #[test]
+    fn span() {
+        let spans = Arc::new(Spans::default());
+        let mut ua = UserAgent::new("http://echo", "key", "secret");
+        ua.set_transport(Echo);
+        ua.add_middleware(Log);
+
+        tracing::subscriber::with_default(spans.clone(), || {
+            ua.post(ua.url("machines")).wait().unwrap();
+        });
+
+        assert_eq!(0, spans.outside.load(SeqCst));
+        assert_eq!(2, spans.inside.load(SeqCst));
+        let recorded = spans.recorded.lock().unwrap();
+        assert!(recorded.contains(&"status".to_string()), "{:?}", recorded);
+        assert!(recorded.contains(&"latency_ms".to_string()), "{:?}", recorded);
+    }
+}
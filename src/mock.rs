@@ -0,0 +1,1056 @@
+//! An in-process openQA server for tests
+//!
+//! It emulates the parts of the API which `OpenQA` wraps: test suites,
+//! machines, products, job groups, job templates, jobs, comments and bugs.
+//! Everything is kept in memory. Signed requests are checked like openQA checks them and
+//! each request is recorded, so tests can seed data, run their code against
+//! `MockServer::client` and then assert on what was sent.
+//!
+//! This needs the `mock` feature.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures::{Future, Stream};
+use hyper::{Body, Chunk, Response, Server, StatusCode};
+use hyper::service::service_fn;
+use http::{self, Method};
+use serde::Serialize;
+use serde_json::{self, Value};
+use tokio::runtime::Runtime;
+use failure::Error;
+use time;
+
+use user_agent::api_hash;
+use lint::glob_match;
+use {Bug, Comment, CommentTarget, Job, JobGroup, JobTemplateInfo, Machine, OpenQA, Product,
+     ProductKey, Setting, Settings, TestSuite};
+
+/// The API key and secret accepted by the server
+pub const KEY: &str = "1234567890ABCDEF";
+pub const SECRET: &str = "FEDCBA0987654321";
+
+/// A request received by the server
+#[derive(Clone, Debug)]
+pub struct Received {
+    pub method: Method,
+    /// The path relative to the API base, e.g. "test_suites/1"
+    pub path: String,
+    /// The decoded query parameters
+    pub params: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Whether the request had a valid signature
+    pub signed: bool,
+}
+
+impl Received {
+    /// The first value of the parameter `key`
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// The `settings[KEY]` parameters
+    pub fn settings(&self) -> Settings {
+        self.params.iter().filter_map(|(k, v)| {
+            if k.starts_with("settings[") && k.ends_with(']') {
+                Some((k[9..k.len() - 1].to_string(), v.clone()))
+            } else {
+                None
+            }
+        }).collect()
+    }
+}
+
+struct Template {
+    group_id: i32,
+    product_id: i32,
+    machine_id: i32,
+    test_suite_id: i32,
+    prio: i32,
+    settings: Settings,
+}
+
+#[derive(Default)]
+struct Store {
+    last_id: i32,
+    test_suites: BTreeMap<i32, TestSuite>,
+    machines: BTreeMap<i32, Machine>,
+    products: BTreeMap<i32, Product>,
+    job_groups: BTreeMap<i32, JobGroup>,
+    job_templates: BTreeMap<i32, Template>,
+    jobs: BTreeMap<i32, Job>,
+    comments: BTreeMap<i32, (CommentTarget, Comment)>,
+    bugs: BTreeMap<i32, Bug>,
+    received: Vec<Received>,
+}
+
+/// An error response, openQA sends these as `{"error": "..."}`
+type Fail = (StatusCode, String);
+
+struct Reply {
+    body: Value,
+    link: Option<String>,
+}
+
+fn ok<T: Serialize>(body: T) -> Result<Reply, Fail> {
+    Ok(Reply { body: serde_json::to_value(body).unwrap(), link: None })
+}
+
+fn bad_request<S: Into<String>>(msg: S) -> Fail {
+    (StatusCode::BAD_REQUEST, msg.into())
+}
+
+fn not_found(what: &str) -> Fail {
+    (StatusCode::NOT_FOUND, format!("{} not found", what))
+}
+
+impl Store {
+    fn next_id(&mut self, id: i32) -> i32 {
+        if id > 0 {
+            self.last_id = self.last_id.max(id);
+            id
+        } else {
+            self.last_id += 1;
+            self.last_id
+        }
+    }
+
+    fn template_info(&self, id: i32, t: &Template) -> Option<JobTemplateInfo> {
+        Some(JobTemplateInfo {
+            group_name: self.job_groups.get(&t.group_id)?.name.clone(),
+            id,
+            machine: self.machines.get(&t.machine_id)?.clone(),
+            prio: t.prio,
+            product: self.products.get(&t.product_id)?.clone(),
+            test_suite: self.test_suites.get(&t.test_suite_id)?.clone(),
+            settings: t.settings.clone(),
+        })
+    }
+
+    fn find<T, F>(map: &BTreeMap<i32, T>, what: &str, f: F) -> Result<i32, Fail>
+    where
+        F: Fn(&T) -> bool,
+    {
+        map.iter().find(|(_, v)| f(v)).map(|(id, _)| *id)
+            .ok_or_else(|| bad_request(format!("{} does not exist", what)))
+    }
+}
+
+struct Params(Vec<(String, String)>);
+
+impl Params {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    fn all<'a>(&'a self, key: &'a str) -> impl Iterator<Item=&'a str> + 'a {
+        self.0.iter().filter(move |(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    fn int(&self, key: &str) -> Result<Option<i32>, Fail> {
+        match self.get(key) {
+            Some(v) => v.parse().map(Some)
+                .map_err(|_| bad_request(format!("{} must be an integer", key))),
+            None => Ok(None),
+        }
+    }
+
+    fn string(&self, key: &str, to: &mut String) {
+        if let Some(v) = self.get(key) {
+            *to = v.to_string();
+        }
+    }
+
+    fn settings(&self) -> Settings {
+        self.0.iter().filter_map(|(k, v)| {
+            if k.starts_with("settings[") && k.ends_with(']') {
+                Some(Setting { key: k[9..k.len() - 1].to_string(), value: v.clone() })
+            } else {
+                None
+            }
+        }).collect()
+    }
+}
+
+/// The tables with an id, name-ish fields and settings
+trait Row: Clone + Serialize {
+    /// The key of the list in responses, e.g. "Machines"
+    const LIST: &'static str;
+
+    fn table(st: &mut Store) -> &mut BTreeMap<i32, Self>;
+    fn blank() -> Self;
+    fn set_id(&mut self, id: i32);
+    /// Set the fields found in `p` and replace the settings
+    fn update(&mut self, p: &Params) -> Result<(), Fail>;
+}
+
+impl Row for TestSuite {
+    const LIST: &'static str = "TestSuites";
+
+    fn table(st: &mut Store) -> &mut BTreeMap<i32, TestSuite> {
+        &mut st.test_suites
+    }
+
+    fn blank() -> TestSuite {
+        TestSuite {
+            description: String::new(),
+            id: 0,
+            name: String::new(),
+            settings: Settings::new(),
+        }
+    }
+
+    fn set_id(&mut self, id: i32) {
+        self.id = id;
+    }
+
+    fn update(&mut self, p: &Params) -> Result<(), Fail> {
+        p.string("name", &mut self.name);
+        p.string("description", &mut self.description);
+        self.settings = p.settings();
+        if self.name.is_empty() {
+            return Err(bad_request("name is required"));
+        }
+        Ok(())
+    }
+}
+
+impl Row for Machine {
+    const LIST: &'static str = "Machines";
+
+    fn table(st: &mut Store) -> &mut BTreeMap<i32, Machine> {
+        &mut st.machines
+    }
+
+    fn blank() -> Machine {
+        Machine {
+            id: 0,
+            name: String::new(),
+            backend: String::new(),
+            settings: Settings::new(),
+        }
+    }
+
+    fn set_id(&mut self, id: i32) {
+        self.id = id;
+    }
+
+    fn update(&mut self, p: &Params) -> Result<(), Fail> {
+        p.string("name", &mut self.name);
+        p.string("backend", &mut self.backend);
+        self.settings = p.settings();
+        if self.name.is_empty() || self.backend.is_empty() {
+            return Err(bad_request("name and backend are required"));
+        }
+        Ok(())
+    }
+}
+
+impl Row for Product {
+    const LIST: &'static str = "Products";
+
+    fn table(st: &mut Store) -> &mut BTreeMap<i32, Product> {
+        &mut st.products
+    }
+
+    fn blank() -> Product {
+        Product {
+            id: 0,
+            arch: String::new(),
+            distri: String::new(),
+            flavor: String::new(),
+            version: String::new(),
+            settings: Settings::new(),
+        }
+    }
+
+    fn set_id(&mut self, id: i32) {
+        self.id = id;
+    }
+
+    fn update(&mut self, p: &Params) -> Result<(), Fail> {
+        p.string("arch", &mut self.arch);
+        p.string("distri", &mut self.distri);
+        p.string("flavor", &mut self.flavor);
+        p.string("version", &mut self.version);
+        self.settings = p.settings();
+        if self.arch.is_empty() || self.distri.is_empty() || self.flavor.is_empty() {
+            return Err(bad_request("arch, distri and flavor are required"));
+        }
+        Ok(())
+    }
+}
+
+fn rows<R: Row>(st: &mut Store, method: &Method, id: Option<i32>, p: &Params)
+                -> Result<Reply, Fail>
+{
+    let mut list = BTreeMap::new();
+
+    match (method, id) {
+        (&Method::GET, None) => {
+            list.insert(R::LIST, R::table(st).values().cloned().collect::<Vec<_>>());
+            ok(list)
+        },
+        (&Method::GET, Some(id)) => {
+            let row = R::table(st).get(&id).cloned().ok_or_else(|| not_found("Row"))?;
+            list.insert(R::LIST, vec![row]);
+            ok(list)
+        },
+        (&Method::POST, None) => {
+            let mut row = R::blank();
+            row.update(p)?;
+            let id = st.next_id(0);
+            row.set_id(id);
+            R::table(st).insert(id, row);
+            ok(json!({ "id": id }))
+        },
+        (&Method::POST, Some(id)) | (&Method::PUT, Some(id)) => {
+            R::table(st).get_mut(&id).ok_or_else(|| not_found("Row"))?.update(p)?;
+            ok(json!({ "result": 1 }))
+        },
+        (&Method::DELETE, Some(id)) => {
+            R::table(st).remove(&id).ok_or_else(|| not_found("Row"))?;
+            ok(json!({ "result": 1 }))
+        },
+        _ => Err(not_found("Route")),
+    }
+}
+
+fn update_group(g: &mut JobGroup, p: &Params) -> Result<(), Fail> {
+    p.string("name", &mut g.name);
+    if let Some(d) = p.get("description") {
+        g.description = Some(d.to_string());
+    }
+    if let Some(id) = p.int("parent_id")? {
+        g.parent_id = Some(id);
+    }
+    for (k, v) in &mut [("default_priority", &mut g.default_priority),
+                        ("sort_order", &mut g.sort_order),
+                        ("size_limit_gb", &mut g.size_limit_gb),
+                        ("keep_logs_in_days", &mut g.keep_logs_in_days),
+                        ("keep_important_logs_in_days", &mut g.keep_important_logs_in_days),
+                        ("keep_results_in_days", &mut g.keep_results_in_days),
+                        ("keep_important_results_in_days",
+                         &mut g.keep_important_results_in_days)] {
+        if let Some(n) = p.int(k)? {
+            **v = Some(n);
+        }
+    }
+    for (k, v) in &mut [("build_version_sort", &mut g.build_version_sort),
+                        ("carry_over_bugrefs", &mut g.carry_over_bugrefs)] {
+        if let Some(b) = p.get(k) {
+            **v = !(b.is_empty() || b == "0");
+        }
+    }
+    if g.name.is_empty() {
+        return Err(bad_request("name is required"));
+    }
+    Ok(())
+}
+
+fn job_groups(st: &mut Store, method: &Method, id: Option<i32>, p: &Params)
+              -> Result<Reply, Fail>
+{
+    match (method, id) {
+        (&Method::GET, None) => ok(st.job_groups.values().collect::<Vec<_>>()),
+        (&Method::GET, Some(id)) => {
+            ok(vec![st.job_groups.get(&id).ok_or_else(|| not_found("Job group"))?])
+        },
+        (&Method::POST, None) => {
+            let mut group = JobGroup::default();
+            update_group(&mut group, p)?;
+            if st.job_groups.values().any(|g| g.name == group.name) {
+                return Err(bad_request("Job group name is already taken"));
+            }
+            group.id = st.next_id(0);
+            let id = group.id;
+            st.job_groups.insert(id, group);
+            ok(json!({ "id": id }))
+        },
+        (&Method::PUT, Some(id)) => {
+            update_group(st.job_groups.get_mut(&id).ok_or_else(|| not_found("Job group"))?, p)?;
+            ok(json!({ "id": id }))
+        },
+        (&Method::DELETE, Some(id)) => {
+            st.job_groups.remove(&id).ok_or_else(|| not_found("Job group"))?;
+            ok(json!({ "id": id }))
+        },
+        _ => Err(not_found("Route")),
+    }
+}
+
+/// Look up an id given directly as `<key>_id` or found by name
+fn lookup<T, F>(p: &Params, map: &BTreeMap<i32, T>, key: &str, f: F) -> Result<i32, Fail>
+where
+    F: Fn(&T) -> bool,
+{
+    match p.int(&format!("{}_id", key))? {
+        Some(id) if map.contains_key(&id) => Ok(id),
+        Some(id) => Err(bad_request(format!("{} {} does not exist", key, id))),
+        None => Store::find(map, key, f),
+    }
+}
+
+/// Create a template, or with `prio_only` change the priority of an existing one
+fn new_template(st: &mut Store, p: &Params, settings: Settings) -> Result<i32, Fail> {
+    let name = |k: &str| p.get(k).unwrap_or_default().to_string();
+    let (group, machine, test_suite) = (name("group_name"), name("machine_name"),
+                                        name("test_suite_name"));
+    let product = ProductKey::new(name("distri"), name("version"), name("flavor"), name("arch"));
+
+    let template = Template {
+        group_id: lookup(p, &st.job_groups, "group", |g| g.name == group)?,
+        product_id: lookup(p, &st.products, "product", |x| ProductKey::from(x) == product)?,
+        machine_id: lookup(p, &st.machines, "machine", |m| m.name == machine)?,
+        test_suite_id: lookup(p, &st.test_suites, "test_suite", |t| t.name == test_suite)?,
+        prio: p.int("prio")?.unwrap_or(50),
+        settings,
+    };
+    let existing = st.job_templates.iter_mut().find(|(_, t)| {
+        (t.product_id, t.machine_id, t.test_suite_id) ==
+            (template.product_id, template.machine_id, template.test_suite_id)
+    });
+    match existing {
+        Some((id, t)) if p.get("prio_only") == Some("1") && t.group_id == template.group_id => {
+            t.prio = template.prio;
+            return Ok(*id);
+        },
+        Some(_) => return Err(bad_request("A job template for this scenario already exists")),
+        None if p.get("prio_only") == Some("1") => return Err(not_found("Job template")),
+        None => (),
+    }
+
+    let id = st.next_id(0);
+    st.job_templates.insert(id, template);
+    Ok(id)
+}
+
+fn job_templates(st: &mut Store, method: &Method, id: Option<i32>, p: &Params)
+                 -> Result<Reply, Fail>
+{
+    match (method, id) {
+        (&Method::GET, _) => {
+            let list: Vec<_> = st.job_templates.iter()
+                .filter(|(i, _)| id.map_or(true, |id| id == **i))
+                .filter_map(|(i, t)| st.template_info(*i, t))
+                .collect();
+            if id.is_some() && list.is_empty() {
+                return Err(not_found("Job template"));
+            }
+            ok(json!({ "JobTemplates": list }))
+        },
+        // Like openQA, this ignores `settings[...]`, which only the group's
+        // YAML can set
+        (&Method::POST, None) => ok(json!({ "id": new_template(st, p, Settings::new())? })),
+        (&Method::DELETE, Some(id)) => {
+            st.job_templates.remove(&id).ok_or_else(|| not_found("Job template"))?;
+            ok(json!({ "result": 1 }))
+        },
+        _ => Err(not_found("Route")),
+    }
+}
+
+fn encode(s: &str) -> String {
+    s.bytes().map(|b| match b {
+        b'0' ..= b'9' | b'A' ..= b'Z' | b'a' ..= b'z' | b'-' | b'_' | b'.' | b'~' => {
+            (b as char).to_string()
+        },
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = ::std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(b) => {
+                        out.push(b);
+                        i += 2;
+                    },
+                    Err(_) => out.push(b'%'),
+                }
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn jobs(st: &mut Store, method: &Method, id: Option<i32>, p: &Params) -> Result<Reply, Fail> {
+    if *method != Method::GET {
+        return Err(not_found("Route"));
+    }
+    if let Some(id) = id {
+        return ok(json!({ "job": st.jobs.get(&id).ok_or_else(|| not_found("Job"))? }));
+    }
+
+    let ids: Vec<i32> = p.all("ids").filter_map(|i| i.parse().ok()).collect();
+    let group = p.int("groupid")?;
+    let mut list: Vec<&Job> = st.jobs.values().rev()
+        .filter(|j| ids.is_empty() || ids.contains(&j.id))
+        .filter(|j| group.map_or(true, |g| j.group_id == Some(g)))
+        .filter(|j| p.get("state").map_or(true, |s| j.state == s))
+        .filter(|j| p.get("result").map_or(true, |r| j.result == r))
+        .filter(|j| p.get("test").map_or(true, |t| j.test == t))
+        .filter(|j| {
+            ["distri", "version", "flavor", "arch", "machine", "build"].iter().all(|k| {
+                let setting = j.settings.get(&k.to_uppercase());
+                p.get(k).map_or(true, |v| setting.map_or(false, |s| s == v))
+            })
+        })
+        .collect();
+
+    let offset = p.int("offset")?.unwrap_or(0).max(0) as usize;
+    let mut link = None;
+    if let Some(limit) = p.int("limit")? {
+        let limit = limit.max(1) as usize;
+        if offset + limit < list.len() {
+            let mut query: Vec<String> = p.0.iter()
+                .filter(|(k, _)| k != "offset")
+                .map(|(k, v)| format!("{}={}", encode(k), encode(v)))
+                .collect();
+            query.push(format!("offset={}", offset + limit));
+            link = Some(format!("</api/v1/jobs?{}>; rel=\"next\"", query.join("&")));
+        }
+        list = list.into_iter().skip(offset).take(limit).collect();
+    }
+
+    let mut reply = ok(json!({ "jobs": list }))?;
+    reply.link = link;
+    Ok(reply)
+}
+
+fn job_settings(st: &Store, p: &Params) -> Result<Reply, Fail> {
+    let key = p.get("key").ok_or_else(|| bad_request("key is required"))?;
+    let value = p.get("list_value").ok_or_else(|| bad_request("list_value is required"))?;
+    let ids: Vec<i32> = st.jobs.values().rev()
+        .filter(|j| j.settings.iter().any(|(k, v)| {
+            glob_match(key, k) && v.split(',').any(|v| v == value)
+        }))
+        .map(|j| j.id)
+        .collect();
+
+    ok(json!({ "jobs": ids }))
+}
+
+fn comments(st: &mut Store, method: &Method, target: CommentTarget, id: Option<i32>, p: &Params)
+            -> Result<Reply, Fail>
+{
+    let text = || p.get("text").map(|t| t.to_string())
+        .ok_or_else(|| bad_request("text is required"));
+
+    match (method, id) {
+        (&Method::GET, None) => {
+            ok(st.comments.values().filter(|(t, _)| *t == target).map(|(_, c)| c)
+               .collect::<Vec<_>>())
+        },
+        (&Method::POST, None) => {
+            let comment = Comment {
+                id: st.next_id(0),
+                text: text()?,
+                user_name: "mock".to_string(),
+                created: String::new(),
+                updated: String::new(),
+                rendered_markdown: String::new(),
+            };
+            let id = comment.id;
+            st.comments.insert(id, (target, comment));
+            ok(json!({ "id": id }))
+        },
+        (&Method::PUT, Some(id)) => {
+            match st.comments.get_mut(&id) {
+                Some((t, c)) if *t == target => c.text = text()?,
+                _ => return Err(not_found("Comment")),
+            }
+            ok(json!({ "id": id }))
+        },
+        (&Method::DELETE, Some(id)) => {
+            match st.comments.get(&id) {
+                Some((t, _)) if *t == target => st.comments.remove(&id),
+                _ => return Err(not_found("Comment")),
+            };
+            ok(json!({ "id": id }))
+        },
+        _ => Err(not_found("Route")),
+    }
+}
+
+/// Bugs have no timestamps here, so `delta` and `created_since` are accepted
+/// but don't filter anything
+fn bugs(st: &mut Store, method: &Method, id: Option<i32>, p: &Params) -> Result<Reply, Fail> {
+    let update = |bug: &mut Bug| {
+        let flag = |k: &str, to: &mut bool| if let Some(v) = p.get(k) { *to = v == "1" };
+        let opt = |k: &str, to: &mut Option<String>| if let Some(v) = p.get(k) {
+            *to = Some(v.to_string())
+        };
+        flag("assigned", &mut bug.assigned);
+        flag("open", &mut bug.open);
+        flag("existing", &mut bug.existing);
+        opt("title", &mut bug.title);
+        opt("priority", &mut bug.priority);
+        opt("assignee", &mut bug.assignee);
+        opt("status", &mut bug.status);
+        opt("resolution", &mut bug.resolution);
+        bug.refreshed = true;
+    };
+
+    match (method, id) {
+        (&Method::GET, None) => {
+            let refreshable = p.get("refreshable") == Some("1");
+            ok(json!({ "bugs": st.bugs.values()
+                       .filter(|b| !refreshable || b.refreshed)
+                       .map(|b| (b.id.to_string(), b.bugid.clone()))
+                       .collect::<BTreeMap<_, _>>() }))
+        },
+        (&Method::GET, Some(id)) => ok(st.bugs.get(&id).ok_or_else(|| not_found("Bug"))?),
+        (&Method::POST, None) => {
+            let bugid = p.get("bugid").ok_or_else(|| bad_request("bugid is required"))?;
+            if st.bugs.values().any(|b| b.bugid == bugid) {
+                return Err(bad_request(format!("Bug {} already exists", bugid)));
+            }
+            let mut bug = Bug { id: st.next_id(0), bugid: bugid.to_string(), ..Bug::default() };
+            update(&mut bug);
+            let id = bug.id;
+            st.bugs.insert(id, bug);
+            ok(json!({ "id": id }))
+        },
+        (&Method::PUT, Some(id)) => {
+            update(st.bugs.get_mut(&id).ok_or_else(|| not_found("Bug"))?);
+            ok(json!({ "id": id }))
+        },
+        (&Method::DELETE, Some(id)) => {
+            st.bugs.remove(&id).ok_or_else(|| not_found("Bug"))?;
+            ok(json!({ "result": 1 }))
+        },
+        _ => Err(not_found("Route")),
+    }
+}
+
+/// The target and comment id of `jobs/<id>/comments[/<id>]` or the same for groups
+fn comment_path(path: &str) -> Result<Option<(CommentTarget, Option<i32>)>, Fail> {
+    let segs: Vec<&str> = path.split('/').collect();
+    let int = |s: &str| s.parse().map_err(|_| not_found("Route"));
+
+    if segs.len() < 3 || segs.len() > 4 || segs[2] != "comments" {
+        return Ok(None);
+    }
+    let target = match segs[0] {
+        "jobs" => CommentTarget::Job(int(segs[1])?),
+        "groups" => CommentTarget::Group(int(segs[1])?),
+        _ => return Ok(None),
+    };
+    let id = match segs.get(3) {
+        Some(id) => Some(int(id)?),
+        None => None,
+    };
+    Ok(Some((target, id)))
+}
+
+fn route(st: &mut Store, method: &Method, path: &str, p: &Params) -> Result<Reply, Fail> {
+    if path == "job_settings/jobs" && *method == Method::GET {
+        return job_settings(st, p);
+    }
+    if let Some((target, id)) = comment_path(path)? {
+        return comments(st, method, target, id, p);
+    }
+
+    let mut segs = path.splitn(2, '/');
+    let table = segs.next().unwrap_or_default();
+    let id = match segs.next() {
+        Some(id) => Some(id.parse().map_err(|_| not_found("Route"))?),
+        None => None,
+    };
+
+    match table {
+        "test_suites" => rows::<TestSuite>(st, method, id, p),
+        "machines" => rows::<Machine>(st, method, id, p),
+        "products" => rows::<Product>(st, method, id, p),
+        "job_groups" => job_groups(st, method, id, p),
+        "job_templates" => job_templates(st, method, id, p),
+        "jobs" => jobs(st, method, id, p),
+        "bugs" => bugs(st, method, id, p),
+        _ => Err(not_found("Route")),
+    }
+}
+
+/// Check the signature headers like openQA does
+fn verify(req: &http::request::Parts) -> Result<(), String> {
+    let hdr = |k| req.headers.get(k).and_then(|v: &http::header::HeaderValue| v.to_str().ok());
+    let (key, time, hash) = match (hdr("X-API-Key"), hdr("X-API-Microtime"), hdr("X-API-Hash")) {
+        (Some(k), Some(t), Some(h)) => (k, t, h),
+        _ => return Err("no api key".to_string()),
+    };
+
+    if key != KEY {
+        return Err("api key not found".to_string());
+    }
+    let sent: i64 = time.parse().map_err(|_| "invalid timestamp".to_string())?;
+    if (time::get_time().sec - sent).abs() > 300 {
+        return Err("timestamp mismatch".to_string());
+    }
+    if hash != api_hash(SECRET, &req.uri, time) {
+        return Err("unknown api key or wrong hash".to_string());
+    }
+    Ok(())
+}
+
+fn respond(store: &Mutex<Store>, req: http::request::Parts, body: Chunk) -> Response<Body> {
+    let path = req.uri.path().trim_start_matches("/api/v1/").trim_end_matches('/').to_string();
+    let params = Params(req.uri.query().unwrap_or_default().split('&')
+        .filter(|kv| !kv.is_empty())
+        .map(|kv| {
+            let mut kv = kv.splitn(2, '=');
+            (decode(kv.next().unwrap_or_default()), decode(kv.next().unwrap_or_default()))
+        })
+        .collect());
+    let signed = verify(&req);
+    let mut st = store.lock().unwrap();
+
+    st.received.push(Received {
+        method: req.method.clone(),
+        path: path.clone(),
+        params: params.0.clone(),
+        body: body.to_vec(),
+        signed: signed.is_ok(),
+    });
+
+    // Reading is allowed without a key, but a bad signature is always refused
+    let needs_auth = req.method != Method::GET || req.headers.contains_key("X-API-Key");
+    let reply = match signed {
+        Err(e) if needs_auth => Err((StatusCode::FORBIDDEN, e)),
+        _ => route(&mut st, &req.method, &path, &params),
+    };
+
+    let (status, reply) = match reply {
+        Ok(r) => (StatusCode::OK, r),
+        Err((status, msg)) => (status, Reply { body: json!({ "error": msg }), link: None }),
+    };
+    let mut res = Response::new(Body::from(serde_json::to_vec(&reply.body).unwrap()));
+    *res.status_mut() = status;
+    res.headers_mut().insert("Content-Type",
+                             http::header::HeaderValue::from_static("application/json"));
+    if let Some(link) = reply.link {
+        res.headers_mut().insert("Link", link.parse().unwrap());
+    }
+    res
+}
+
+/// A running mock server, which stops when dropped
+pub struct MockServer {
+    addr: SocketAddr,
+    store: Arc<Mutex<Store>>,
+    runtime: Option<Runtime>,
+}
+
+impl MockServer {
+    /// Listen on a free port of the loopback interface
+    pub fn start() -> Result<MockServer, Error> {
+        let store = Arc::new(Mutex::new(Store::default()));
+        let shared = store.clone();
+        let server = Server::try_bind(&([127, 0, 0, 1], 0).into())?
+            .serve(move || {
+                let store = shared.clone();
+                service_fn(move |req: http::Request<Body>| {
+                    let store = store.clone();
+                    let (parts, body) = req.into_parts();
+                    body.concat2().map(move |body| respond(&store, parts, body))
+                })
+            });
+        let addr = server.local_addr();
+        let mut runtime = Runtime::new()?;
+
+        runtime.spawn(server.map_err(|e| error!("Mock openQA server: {}", e)));
+        Ok(MockServer { addr, store, runtime: Some(runtime) })
+    }
+
+    /// The host to pass to `OpenQA::new`, e.g. "http://127.0.0.1:34567"
+    pub fn host(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// A client for this server with the right key and secret
+    pub fn client(&self) -> OpenQA {
+        OpenQA::new(self.host(), KEY, SECRET)
+    }
+
+    /// Run `f` on the server's runtime until it completes
+    ///
+    /// The `OpenQA` client needs a runtime to send requests, so use this
+    /// instead of `wait()`.
+    pub fn block_on<F>(&mut self, f: F) -> Result<F::Item, F::Error>
+    where
+        F: Future + Send + 'static,
+        F::Item: Send + 'static,
+        F::Error: Send + 'static,
+    {
+        self.runtime.as_mut().unwrap().block_on(f)
+    }
+
+    fn insert<R: Row>(&self, mut row: R, id: i32) -> i32 {
+        let mut st = self.store.lock().unwrap();
+        let id = st.next_id(id);
+
+        row.set_id(id);
+        R::table(&mut st).insert(id, row);
+        id
+    }
+
+    /// Add a test suite, giving it a new id unless it has one, and return
+    /// its id
+    pub fn add_test_suite(&self, test_suite: TestSuite) -> i32 {
+        let id = test_suite.id;
+        self.insert(test_suite, id)
+    }
+
+    pub fn add_machine(&self, machine: Machine) -> i32 {
+        let id = machine.id;
+        self.insert(machine, id)
+    }
+
+    pub fn add_product(&self, product: Product) -> i32 {
+        let id = product.id;
+        self.insert(product, id)
+    }
+
+    pub fn add_job_group(&self, mut group: JobGroup) -> i32 {
+        let mut st = self.store.lock().unwrap();
+
+        group.id = st.next_id(group.id);
+        let id = group.id;
+        st.job_groups.insert(id, group);
+        id
+    }
+
+    /// Add a template for the group, product, machine and test suite with
+    /// the same names, which must already exist
+    pub fn add_job_template(&self, template: &JobTemplateInfo) -> Result<i32, Error> {
+        let params: Vec<(String, String)> = vec![
+            ("group_name", template.group_name.clone()),
+            ("machine_name", template.machine.name.clone()),
+            ("test_suite_name", template.test_suite.name.clone()),
+            ("arch", template.product.arch.clone()),
+            ("distri", template.product.distri.clone()),
+            ("flavor", template.product.flavor.clone()),
+            ("version", template.product.version.clone()),
+            ("prio", template.prio.to_string()),
+        ].into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+
+        new_template(&mut self.store.lock().unwrap(), &Params(params), template.settings.clone())
+            .map_err(|(_, e)| format_err!("{}", e))
+    }
+
+    /// Add or replace a job
+    pub fn add_job(&self, job: Job) {
+        let mut st = self.store.lock().unwrap();
+
+        st.next_id(job.id);
+        st.jobs.insert(job.id, job);
+    }
+
+    /// Change a job, for example to make it finish
+    pub fn update_job<F: FnOnce(&mut Job)>(&self, id: i32, f: F) -> Result<(), Error> {
+        let mut st = self.store.lock().unwrap();
+        let job = st.jobs.get_mut(&id).ok_or_else(|| format_err!("No job {}", id))?;
+
+        f(job);
+        Ok(())
+    }
+
+    /// The requests received so far, oldest first
+    pub fn received(&self) -> Vec<Received> {
+        self.store.lock().unwrap().received.clone()
+    }
+
+    pub fn clear_received(&self) {
+        self.store.lock().unwrap().received.clear();
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(rt) = self.runtime.take() {
+            let _ = rt.shutdown_now().wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {BugFilter, BugRef, CreateResult, JobTemplate, UpdateResult};
+
+    fn job(id: i32, group_id: i32) -> Job {
+        Job::fake(id, "ltp").with_group(group_id, "LTP")
+    }
+
+    #[test]
+    fn tables() {
+        let mut server = MockServer::start().unwrap();
+        let oqa = server.client();
+        let machine = Machine {
+            id: 0,
+            name: "64bit".to_string(),
+            backend: "qemu".to_string(),
+            settings: vec![("QEMUCPU", "host")].into_iter().collect(),
+        };
+
+        let id = match server.block_on(oqa.new_machine(&machine)).unwrap() {
+            CreateResult::Ok(id) => id,
+            CreateResult::Err(e) => panic!("{}", e),
+        };
+        let res = server.block_on(oqa.get_machines()).unwrap();
+        assert_eq!(1, res.machines.len());
+        assert_eq!(id, res.machines[0].id);
+        assert_eq!(Some("host"), res.machines[0].settings.get("QEMUCPU"));
+
+        let req = server.received().pop().unwrap();
+        assert_eq!(Method::GET, req.method);
+        assert_eq!("machines", req.path);
+        let req = &server.received()[0];
+        assert_eq!(Method::POST, req.method);
+        assert!(req.signed);
+        assert_eq!(Some("64bit"), req.param("name"));
+        assert_eq!(Some("host"), req.settings().get("QEMUCPU"));
+
+        match server.block_on(oqa.del_machine(id)).unwrap() {
+            UpdateResult::Ok(_) => (),
+            UpdateResult::Err(e) => panic!("{}", e),
+        }
+        assert!(server.block_on(oqa.get_machines()).unwrap().machines.is_empty());
+
+        let bad = OpenQA::new(server.host(), KEY, "wrong");
+        match server.block_on(bad.new_machine(&machine)).unwrap() {
+            CreateResult::Err(e) => assert_eq!("unknown api key or wrong hash", e),
+            CreateResult::Ok(_) => panic!("Accepted a bad signature"),
+        }
+    }
+
+    #[test]
+    fn job_templates() {
+        let mut server = MockServer::start().unwrap();
+        let oqa = server.client();
+        let group_id = server.add_job_group(JobGroup {
+            name: "Kernel".to_string(),
+            ..JobGroup::default()
+        });
+        let template = JobTemplate {
+            group_id,
+            product_id: server.add_product(Product {
+                id: 0,
+                arch: "x86_64".to_string(),
+                distri: "sle".to_string(),
+                flavor: "Server-DVD".to_string(),
+                version: "15".to_string(),
+                settings: Settings::new(),
+            }),
+            machine_id: server.add_machine(Machine {
+                id: 0,
+                name: "64bit".to_string(),
+                backend: "qemu".to_string(),
+                settings: Settings::new(),
+            }),
+            test_suite_id: server.add_test_suite(TestSuite {
+                description: String::new(),
+                id: 0,
+                name: "ltp".to_string(),
+                settings: Settings::new(),
+            }),
+        };
+
+        server.block_on(oqa.new_job_template(&template)).unwrap().into_result().unwrap();
+        let templates = server.block_on(oqa.get_job_templates()).unwrap().job_templates;
+        assert_eq!(1, templates.len());
+        assert_eq!("Kernel", templates[0].group_name);
+        assert_eq!("ltp", templates[0].test_suite.name);
+
+        assert!(server.add_job_template(&templates[0]).is_err());
+        let res = server.block_on(oqa.new_job_template(&template)).unwrap();
+        assert!(res.into_result().is_err());
+    }
+
+    #[test]
+    fn paged_jobs() {
+        let mut server = MockServer::start().unwrap();
+        let oqa = server.client();
+
+        for id in 1..6 {
+            server.add_job(job(id, 1));
+        }
+        server.add_job(job(6, 2));
+
+        let jobs = server.block_on(oqa.get_all_jobs(&[("groupid", "1")], 2).collect()).unwrap();
+        let ids: Vec<i32> = jobs.iter().map(|j| j.id).collect();
+        assert_eq!(vec![5, 4, 3, 2, 1], ids);
+        assert_eq!(3, server.received().len());
+        assert_eq!(Some("4"), server.received()[2].param("offset"));
+
+        let ids = server.block_on(oqa.search_job_settings("TE*", "ltp")).unwrap();
+        assert_eq!(vec![6, 5, 4, 3, 2, 1], ids);
+    }
+
+    #[test]
+    fn comments() {
+        let mut server = MockServer::start().unwrap();
+        let oqa = server.client();
+        let job = CommentTarget::Job(3);
+
+        let id = server.block_on(oqa.new_comment(job, "bsc#1 fails")).unwrap()
+            .into_result().unwrap();
+        server.block_on(oqa.new_comment(CommentTarget::Group(3), "other")).unwrap()
+            .into_result().unwrap();
+        let req = &server.received()[0];
+        assert_eq!((Method::POST, "jobs/3/comments"), (req.method.clone(), req.path.as_str()));
+        assert!(req.signed);
+
+        let res = server.block_on(oqa.upd_comment(job, id, "bnc#2 fails")).unwrap();
+        assert_eq!(id, res.into_result().unwrap());
+        let comments = server.block_on(oqa.get_comments(job)).unwrap();
+        assert_eq!(1, comments.len());
+        assert_eq!(vec![BugRef::Bnc(2)], comments[0].bugrefs());
+
+        assert!(server.block_on(oqa.del_comment(CommentTarget::Group(3), id)).unwrap()
+                .into_result().is_err());
+        server.block_on(oqa.del_comment(job, id)).unwrap().into_result().unwrap();
+        assert_eq!(Method::DELETE, server.received().pop().unwrap().method);
+        assert!(server.block_on(oqa.get_comments(job)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bugs() {
+        let mut server = MockServer::start().unwrap();
+        let oqa = server.client();
+        let mut bug = Bug { bugid: "bsc#1".to_string(), open: true, ..Bug::default() };
+
+        bug.id = server.block_on(oqa.new_bug(&bug)).unwrap().into_result().unwrap();
+        assert!(server.block_on(oqa.new_bug(&bug)).unwrap().into_result().is_err());
+        bug.title = Some("Boot fails".to_string());
+        server.block_on(oqa.upd_bug(&bug)).unwrap().into_result().unwrap();
+        assert_eq!(Some("Boot fails"), server.received()[2].param("title"));
+
+        let got = server.block_on(oqa.get_bug(bug.id)).unwrap();
+        assert_eq!(("bsc#1", true, true), (got.bugid.as_str(), got.open, got.refreshed));
+        assert_eq!(BugRef::Bsc(1), got.bugref().unwrap());
+
+        let bugs = server.block_on(oqa.get_bugs(BugFilter::Refreshable(Some(60)))).unwrap();
+        assert_eq!(Some(&"bsc#1".to_string()), bugs.bugs.get(&bug.id));
+        let req = server.received().pop().unwrap();
+        assert_eq!((Some("1"), Some("60")), (req.param("refreshable"), req.param("delta")));
+
+        server.block_on(oqa.get_bugs(BugFilter::CreatedWithin(3600))).unwrap();
+        let req = server.received().pop().unwrap();
+        assert_eq!((Some("3600"), None), (req.param("created_since"), req.param("delta")));
+
+        server.block_on(oqa.del_bug(bug.id)).unwrap().into_result().unwrap();
+        assert!(server.block_on(oqa.get_bugs(BugFilter::All)).unwrap().bugs.is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+//! Summarise job results by build and compare builds
+//!
+//! This works on job lists fetched with e.g. `get_build_jobs` or
+//! `get_all_jobs`. Within a build only the latest job of each scenario
+//! counts, so restarted jobs don't count twice.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use {Job, Scenario, TestModule};
+
+/// What a job's result means for triage, following openQA's own grouping
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum Verdict {
+    Passed,
+    Softfailed,
+    Failed,
+    /// Incomplete or timed out
+    Incomplete,
+    /// Cancelled, obsoleted, skipped or restarted
+    Aborted,
+    /// Not done yet
+    Pending,
+}
+
+impl Verdict {
+    pub fn of(job: &Job) -> Verdict {
+        if job.state != "done" && job.state != "cancelled" {
+            return Verdict::Pending;
+        }
+        match job.result.as_str() {
+            "passed" => Verdict::Passed,
+            "softfailed" => Verdict::Softfailed,
+            "failed" => Verdict::Failed,
+            "incomplete" | "timeout_exceeded" => Verdict::Incomplete,
+            _ => Verdict::Aborted,
+        }
+    }
+
+    /// The verdict of a test module, which is never pending
+    pub fn of_module(module: &TestModule) -> Verdict {
+        match module.result.as_str() {
+            "passed" => Verdict::Passed,
+            "softfailed" => Verdict::Softfailed,
+            "failed" => Verdict::Failed,
+            "incomplete" => Verdict::Incomplete,
+            _ => Verdict::Aborted,
+        }
+    }
+
+    /// Passed or softfailed
+    pub fn is_ok(self) -> bool {
+        self == Verdict::Passed || self == Verdict::Softfailed
+    }
+
+    /// Failed or incomplete
+    pub fn is_failure(self) -> bool {
+        self == Verdict::Failed || self == Verdict::Incomplete
+    }
+}
+
+/// The job's `BUILD` setting or an empty string
+pub fn build_of(job: &Job) -> &str {
+    job.settings.get("BUILD").map(|b| b.as_str()).unwrap_or_default()
+}
+
+/// The job's scenario, e.g. `opensuse-Tumbleweed-DVD-x86_64-textmode@64bit`
+pub fn scenario_of(job: &Job) -> String {
+    Scenario::from(job).to_string()
+}
+
+/// Group jobs by their build
+pub fn by_build(jobs: &[Job]) -> BTreeMap<&str, Vec<&Job>> {
+    let mut builds: BTreeMap<&str, Vec<&Job>> = BTreeMap::new();
+
+    for job in jobs {
+        builds.entry(build_of(job)).or_default().push(job);
+    }
+    builds
+}
+
+/// Group jobs by their scenario
+pub fn by_scenario<'a, I>(jobs: I) -> BTreeMap<Scenario, Vec<&'a Job>>
+where
+    I: IntoIterator<Item=&'a Job>,
+{
+    let mut scenarios: BTreeMap<Scenario, Vec<&Job>> = BTreeMap::new();
+
+    for job in jobs {
+        scenarios.entry(Scenario::from(job)).or_default().push(job);
+    }
+    scenarios
+}
+
+/// The newest job of each scenario
+pub fn latest<'a, I: IntoIterator<Item=&'a Job>>(jobs: I) -> BTreeMap<Scenario, &'a Job> {
+    by_scenario(jobs).into_iter()
+        .filter_map(|(s, jobs)| jobs.into_iter().max_by_key(|j| j.id).map(|j| (s, j)))
+        .collect()
+}
+
+/// The builds from newest to oldest, by their newest job
+pub fn builds(jobs: &[Job]) -> Vec<&str> {
+    let mut builds: Vec<_> = by_build(jobs).into_iter()
+        .map(|(b, jobs)| (jobs.iter().map(|j| j.id).max().unwrap_or(0), b))
+        .collect();
+
+    builds.sort_by(|a, b| b.cmp(a));
+    builds.into_iter().map(|(_, b)| b).collect()
+}
+
+/// How many jobs had each verdict
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub passed: usize,
+    pub softfailed: usize,
+    pub failed: usize,
+    pub incomplete: usize,
+    pub aborted: usize,
+    pub pending: usize,
+}
+
+impl Summary {
+    /// Count the latest job of each scenario
+    pub fn of<'a, I: IntoIterator<Item=&'a Job>>(jobs: I) -> Summary {
+        let mut sum = Summary::default();
+
+        for job in latest(jobs).values() {
+            sum.add(Verdict::of(job));
+        }
+        sum
+    }
+
+    pub fn add(&mut self, verdict: Verdict) {
+        *match verdict {
+            Verdict::Passed => &mut self.passed,
+            Verdict::Softfailed => &mut self.softfailed,
+            Verdict::Failed => &mut self.failed,
+            Verdict::Incomplete => &mut self.incomplete,
+            Verdict::Aborted => &mut self.aborted,
+            Verdict::Pending => &mut self.pending,
+        } += 1;
+    }
+
+    pub fn total(&self) -> usize {
+        self.passed + self.softfailed + self.failed + self.incomplete + self.aborted + self.pending
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} passed, {} softfailed, {} failed, {} incomplete, {} aborted, {} pending",
+               self.passed, self.softfailed, self.failed, self.incomplete, self.aborted,
+               self.pending)
+    }
+}
+
+/// A summary of each build
+pub fn summarize(jobs: &[Job]) -> BTreeMap<&str, Summary> {
+    by_build(jobs).into_iter().map(|(b, jobs)| (b, Summary::of(jobs))).collect()
+}
+
+/// A scenario's latest job in two builds
+#[derive(Clone, Debug)]
+pub struct Change<'a> {
+    pub scenario: Scenario,
+    pub before: Option<&'a Job>,
+    pub after: Option<&'a Job>,
+}
+
+/// How the results of a build differ from an earlier one
+#[derive(Clone, Debug, Default)]
+pub struct BuildDiff<'a> {
+    /// Failing now, but passed before
+    pub new_failures: Vec<Change<'a>>,
+    /// Passing now, but failed before
+    pub fixed: Vec<Change<'a>>,
+    pub still_failing: Vec<Change<'a>>,
+    /// Only in the later build
+    pub added: Vec<Change<'a>>,
+    /// Only in the earlier build
+    pub removed: Vec<Change<'a>>,
+}
+
+/// Compare the latest jobs of each scenario in `before` and `after`
+///
+/// Scenarios which are aborted or still running in either build are only
+/// reported if they were added or removed.
+pub fn diff<'a, B, A>(before: B, after: A) -> BuildDiff<'a>
+where
+    B: IntoIterator<Item=&'a Job>,
+    A: IntoIterator<Item=&'a Job>,
+{
+    let before = latest(before);
+    let mut after = latest(after);
+    let mut diff = BuildDiff::default();
+
+    for (scenario, old) in before {
+        let new = after.remove(&scenario);
+        let (was, is) = (Verdict::of(old), new.map(Verdict::of));
+        let change = Change { scenario, before: Some(old), after: new };
+
+        match is {
+            None => diff.removed.push(change),
+            Some(is) if is.is_failure() && was.is_ok() => diff.new_failures.push(change),
+            Some(is) if is.is_failure() && was.is_failure() => diff.still_failing.push(change),
+            Some(is) if is.is_ok() && was.is_failure() => diff.fixed.push(change),
+            _ => (),
+        }
+    }
+    diff.added = after.into_iter()
+        .map(|(scenario, new)| Change { scenario, before: None, after: Some(new) })
+        .collect();
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(id: i32, build: &str, test: &str, result: &str) -> Job {
+        Job::fake(id, test).with_result(result).with_group(1, "Tumbleweed")
+            .with_setting("BUILD", build)
+    }
+
+    fn jobs() -> Vec<Job> {
+        vec![
+            job(1, "20240101", "textmode", "passed"),
+            job(2, "20240101", "kde", "failed"),
+            job(3, "20240101", "gnome", "passed"),
+            job(4, "20240101", "xfce", "incomplete"),
+            job(5, "20240102", "textmode", "failed"),
+            job(6, "20240102", "kde", "softfailed"),
+            job(7, "20240102", "xfce", "failed"),
+            job(8, "20240102", "minimal", "none"),
+            // A restart of the first textmode failure
+            job(9, "20240102", "textmode", "user_cancelled"),
+            job(10, "20240102", "textmode", "failed"),
+        ]
+    }
+
+    #[test]
+    fn summaries() {
+        let jobs = jobs();
+        let sums = summarize(&jobs);
+
+        assert_eq!(vec!["20240102", "20240101"], builds(&jobs));
+        assert_eq!("2 passed, 0 softfailed, 1 failed, 1 incomplete, 0 aborted, 0 pending",
+                   sums["20240101"].to_string());
+        assert_eq!(Summary { softfailed: 1, failed: 2, pending: 1, ..Summary::default() },
+                   sums["20240102"]);
+        assert_eq!(4, sums["20240102"].total());
+        assert_eq!("opensuse-Tumbleweed-DVD-x86_64-kde@64bit", scenario_of(&jobs[1]));
+    }
+
+    #[test]
+    fn build_diff() {
+        let jobs = jobs();
+        let builds = by_build(&jobs);
+        let diff = diff(builds["20240101"].iter().cloned(), builds["20240102"].iter().cloned());
+        let names = |c: &[Change]| -> Vec<String> {
+            c.iter().map(|c| c.after.or(c.before).unwrap().test.clone()).collect()
+        };
+
+        assert_eq!(vec!["textmode"], names(&diff.new_failures));
+        assert_eq!(Some(10), diff.new_failures[0].after.map(|j| j.id));
+        assert_eq!("opensuse-Tumbleweed-DVD-x86_64-textmode@64bit",
+                   diff.new_failures[0].scenario.to_string());
+        assert_eq!(vec!["kde"], names(&diff.fixed));
+        assert_eq!(vec!["xfce"], names(&diff.still_failing));
+        assert_eq!(vec!["minimal"], names(&diff.added));
+        assert_eq!(vec!["gnome"], names(&diff.removed));
+    }
+}
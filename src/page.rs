@@ -0,0 +1,123 @@
+//! Follow paginated list responses
+//!
+//! Newer openQA versions return at most `limit` items per request along
+//! with a `Link` header pointing at the next page. Older versions, and
+//! endpoints which don't paginate, return everything at once without the
+//! header.
+
+use futures::{stream, Stream};
+use hyper::rt::Future;
+use hyper::Chunk;
+use http::header::LINK;
+use http::uri::Uri;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+use failure::Error;
+
+use {parse_body, UserAgent};
+
+/// Find the URL with `rel="next"` in a `Link` header value
+pub fn next_link(header: &str) -> Option<&str> {
+    let mut rest = header;
+
+    while let Some(start) = rest.find('<') {
+        let end = start + rest[start..].find('>')?;
+        let url = &rest[start + 1..end];
+        let params = rest[end + 1..].split(',').next().unwrap_or_default();
+
+        rest = &rest[end + 1..];
+        let next = params.split(';')
+            .filter_map(|p| {
+                let mut kv = p.splitn(2, '=');
+                match (kv.next(), kv.next()) {
+                    (Some(k), Some(v)) if k.trim().eq_ignore_ascii_case("rel") => Some(v),
+                    _ => None,
+                }
+            })
+            .any(|v| v.trim().trim_matches('"').split_whitespace().any(|r| r == "next"));
+        if next {
+            return Some(url);
+        }
+    }
+
+    None
+}
+
+/// Resolve `link` against the URL of the page it was found on
+fn next_uri(page: &Uri, link: &str) -> Result<Uri, Error> {
+    if link.starts_with('/') {
+        let scheme = page.scheme_part().map(|s| s.as_str()).unwrap_or("http");
+        let authority = page.authority_part().map(|a| a.as_str()).unwrap_or_default();
+        format!("{}://{}{}", scheme, authority, link).parse()
+    } else {
+        link.parse()
+    }.map_err(|e| format_err!("Invalid next page link {}: {}", link, e))
+}
+
+/// The items of a page, which is either a list or, when `field` is given,
+/// an object with the list in that field, e.g. `{"jobs": [...]}`
+fn items<T: DeserializeOwned>(body: &Chunk, field: Option<&str>) -> Result<Vec<T>, Error> {
+    let list = match (parse_body(body)?, field) {
+        (Value::Object(mut map), Some(f)) => map.remove(f)
+            .ok_or_else(|| format_err!("Page does not contain '{}'", f))?,
+        (v, _) => v,
+    };
+
+    serde_json::from_value(list).map_err(|e| format_err!("Deserializing page: {}", e))
+}
+
+/// Stream the items of every page, starting with `first`, taking them from
+/// `field` of each page if it isn't a plain list
+pub fn pages<T>(ua: UserAgent, first: Uri, field: Option<&str>)
+                -> impl Stream<Item=T, Error=Error>
+where
+    T: DeserializeOwned,
+{
+    let field = field.map(|f| f.to_string());
+
+    stream::unfold(Some(first), move |uri| {
+        let field = field.clone();
+        uri.map(|uri| ua.get_response(uri.clone()).and_then(move |(parts, body)| {
+            let next = match parts.headers.get(LINK).and_then(|h| h.to_str().ok()) {
+                Some(h) => match next_link(h) {
+                    Some(link) => Some(next_uri(&uri, link)?),
+                    None => None,
+                },
+                None => None,
+            };
+            debug!("GET {} (page), next {:?}", uri, next);
+
+            Ok((items(&body, field.as_deref())?, next))
+        }))
+    }).map(stream::iter_ok).flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_header() {
+        let h = r#"<https://o3/api/v1/jobs?limit=2&offset=0>; rel="first", <https://o3/api/v1/jobs?limit=2&offset=2>; rel="next""#;
+        assert_eq!(Some("https://o3/api/v1/jobs?limit=2&offset=2"), next_link(h));
+        assert_eq!(Some("/a"), next_link("</a>;rel=next"));
+        assert_eq!(Some("/b"), next_link(r#"</a>; rel="prev", </b>; title="x"; rel="last next""#));
+        assert_eq!(None, next_link(r#"</a>; rel="prev""#));
+        assert_eq!(None, next_link(""));
+
+        let page: Uri = "https://o3:8080/api/v1/jobs?limit=2".parse().unwrap();
+        assert_eq!("https://o3:8080/api/v1/jobs?offset=2",
+                   next_uri(&page, "/api/v1/jobs?offset=2").unwrap().to_string());
+    }
+
+    #[test]
+    fn page_items() {
+        let page = Chunk::from(r#"{"flags": [true], "jobs": [1, 2]}"#);
+        let jobs: Vec<i32> = items(&page, Some("jobs")).unwrap();
+        assert_eq!(vec![1, 2], jobs);
+        let list: Vec<i32> = items(&Chunk::from("[3]"), None).unwrap();
+        assert_eq!(vec![3], list);
+        assert!(items::<i32>(&Chunk::from(r#"{"count": 1}"#), Some("jobs")).is_err());
+        assert!(items::<i32>(&page, None).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Limit the rate of requests sent to a host
+//!
+//! This is a token bucket: it holds up to `burst` tokens, gains `per_second`
+//! tokens each second and each request takes one. When a server replies
+//! with 429 Too Many Requests, its `Retry-After` header pauses the bucket.
+
+use std::cmp;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either};
+use futures::Future;
+use http::header::{HeaderMap, RETRY_AFTER};
+use failure::Error;
+use time;
+use tokio_timer::Delay;
+
+/// Requests per second and how many can be sent at once after a quiet period
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(per_second: f64, burst: u32) -> RateLimit {
+        RateLimit { per_second, burst: cmp::max(burst, 1) }
+    }
+}
+
+struct State {
+    tokens: f64,
+    last: Instant,
+    paused_until: Option<Instant>,
+}
+
+pub struct Limiter {
+    limit: RateLimit,
+    state: Mutex<State>,
+}
+
+impl Limiter {
+    pub fn new(limit: RateLimit) -> Limiter {
+        Limiter {
+            limit,
+            state: Mutex::new(State {
+                tokens: f64::from(limit.burst),
+                last: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Take a token, returning how long to wait before using it
+    pub fn acquire(&self) -> Duration {
+        self.acquire_at(Instant::now())
+    }
+
+    fn acquire_at(&self, now: Instant) -> Duration {
+        let mut st = self.state.lock().unwrap();
+        let elapsed = now.saturating_duration_since(st.last);
+
+        st.tokens = (st.tokens + secs(elapsed) * self.limit.per_second)
+            .min(f64::from(self.limit.burst)) - 1.0;
+        st.last = now;
+
+        let mut wait = if st.tokens >= 0.0 || self.limit.per_second <= 0.0 {
+            Duration::default()
+        } else {
+            Duration::from_millis((-st.tokens / self.limit.per_second * 1000.0).ceil() as u64)
+        };
+        if let Some(until) = st.paused_until {
+            wait = cmp::max(wait, until.saturating_duration_since(now));
+        }
+        wait
+    }
+
+    /// Stop handing out tokens for `wait`
+    pub fn pause(&self, wait: Duration) {
+        let until = Instant::now() + wait;
+        let mut st = self.state.lock().unwrap();
+
+        st.paused_until = Some(st.paused_until.map_or(until, |u| cmp::max(u, until)));
+    }
+}
+
+fn secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9
+}
+
+/// The delay requested by a `Retry-After` header, in seconds or as a date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = time::strptime(value, "%a, %d %b %Y %H:%M:%S GMT").ok()?.to_timespec();
+    let now = time::get_time();
+    Some(Duration::from_secs(cmp::max(at.sec - now.sec, 0) as u64))
+}
+
+/// A future which completes after `wait`
+///
+/// This uses the timer of the tokio runtime it is polled on, so it fails
+/// outside a runtime, and never completes if `wait()` blocks the runtime's
+/// own thread.
+pub fn delay(wait: Duration) -> impl Future<Item=(), Error=Error> {
+    if wait == Duration::default() {
+        return Either::A(future::ok(()));
+    }
+    Either::B(Delay::new(Instant::now() + wait).map_err(|e| format_err!("Delay failed: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::header::HeaderValue;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn bucket() {
+        let lim = Limiter::new(RateLimit::new(2.0, 2));
+        let start = Instant::now();
+
+        assert_eq!(Duration::default(), lim.acquire_at(start));
+        assert_eq!(Duration::default(), lim.acquire_at(start));
+        assert_eq!(Duration::from_millis(500), lim.acquire_at(start));
+        assert_eq!(Duration::from_millis(1000), lim.acquire_at(start));
+        // Two tokens were borrowed, one second repays them
+        assert_eq!(Duration::from_millis(500), lim.acquire_at(start + Duration::from_secs(1)));
+
+        lim.pause(Duration::from_secs(60));
+        assert!(lim.acquire() > Duration::from_secs(59));
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let mut hdrs = HeaderMap::new();
+        assert_eq!(None, retry_after(&hdrs));
+
+        hdrs.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(Some(Duration::from_secs(120)), retry_after(&hdrs));
+
+        hdrs.insert(RETRY_AFTER, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(Some(Duration::default()), retry_after(&hdrs));
+    }
+
+    #[test]
+    fn delays() {
+        let mut rt = Runtime::new().unwrap();
+        let start = Instant::now();
+
+        rt.block_on(delay(Duration::from_millis(20))).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(delay(Duration::from_millis(20)).wait().is_err());
+    }
+}
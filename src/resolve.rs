@@ -0,0 +1,211 @@
+//! Compute the settings a job will get when its template is scheduled
+//!
+//! This mirrors what openQA does when an ISO is posted: product settings are
+//! overridden by machine settings, then test suite settings, then job
+//! template settings and finally the parameters posted to `isos`.
+//! `WORKER_CLASS` is instead merged from all of the sources and `%VAR%`
+//! placeholders are expanded last.
+
+use std::collections::BTreeSet;
+
+use failure::Error;
+
+use {JobTemplateInfo, Machine, Product, Settings, TestSuite};
+
+fn expand(settings: &Settings, key: &str, path: &mut Vec<String>) -> Result<String, Error> {
+    let value = settings.get(key).unwrap_or_default();
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+
+    path.push(key.to_uppercase());
+    while let Some(start) = rest.find('%') {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start + 1..];
+        let end = match tail.find('%') {
+            Some(end) => end,
+            None => {
+                rest = &rest[start..];
+                break;
+            },
+        };
+        let name = &tail[..end];
+
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            // Not a placeholder, keep the first '%' and retry from the second
+            out.push('%');
+            rest = tail;
+            continue;
+        }
+
+        if path.iter().any(|p| p.eq_ignore_ascii_case(name)) {
+            bail!("The key {} contains a circular reference, its value is {}",
+                  name, settings.get(name).unwrap_or_default());
+        }
+        if settings.contains(name) {
+            out.push_str(&expand(settings, name, path)?);
+        } else {
+            out.push('%');
+            out.push_str(name);
+            out.push('%');
+        }
+        rest = &tail[end + 1..];
+    }
+    out.push_str(rest);
+    path.pop();
+
+    Ok(out)
+}
+
+/// Replace `%VAR%` in each value with the (expanded) value of `VAR`
+///
+/// Placeholders for settings which don't exist are left as they are.
+pub fn expand_placeholders(settings: &mut Settings) -> Result<(), Error> {
+    let expanded = settings.iter()
+        .map(|s| expand(settings, &s.key, &mut Vec::new()).map(|v| (s.key.clone(), v)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    for (k, v) in expanded {
+        settings.set(k, v);
+    }
+
+    Ok(())
+}
+
+/// The effective settings of a job created from `template`
+///
+/// `args` are the parameters which would be posted with `schedule_iso`, pass
+/// an empty `Settings` to preview the template on its own. The result is
+/// sorted by key.
+pub fn resolve_settings(template: &JobTemplateInfo,
+                        product: &Product,
+                        machine: &Machine,
+                        test_suite: &TestSuite,
+                        args: &Settings) -> Result<Settings, Error>
+{
+    let mut settings = Settings::new();
+    let mut classes = BTreeSet::new();
+
+    settings.set("DISTRI", product.distri.as_str());
+    settings.set("VERSION", product.version.as_str());
+    settings.set("FLAVOR", product.flavor.as_str());
+    settings.set("ARCH", product.arch.as_str());
+
+    for sets in &[&product.settings, &machine.settings,
+                  &test_suite.settings, &template.settings] {
+        for s in sets.iter() {
+            if s.key.eq_ignore_ascii_case("WORKER_CLASS") {
+                classes.extend(s.value.split(',').map(|c| c.trim().to_string()));
+            } else {
+                settings.set(s.key.to_uppercase(), s.value.as_str());
+            }
+        }
+    }
+    classes.remove("");
+    if !classes.is_empty() {
+        let classes: Vec<_> = classes.into_iter().collect();
+        settings.set("WORKER_CLASS", classes.join(","));
+    }
+
+    settings.set("TEST", test_suite.name.as_str());
+    settings.set("MACHINE", machine.name.as_str());
+    if !machine.backend.is_empty() {
+        settings.set("BACKEND", machine.backend.as_str());
+    }
+
+    for s in args {
+        settings.set(s.key.to_uppercase(), s.value.as_str());
+    }
+
+    if let Some(d) = settings.get("DISTRI").map(|d| d.to_lowercase()) {
+        settings.set("DISTRI", d);
+    }
+    expand_placeholders(&mut settings)?;
+
+    let mut sorted = settings.into_vec();
+    sorted.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(sorted.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pairs: &[(&str, &str)]) -> Settings {
+        pairs.iter().cloned().collect()
+    }
+
+    #[test]
+    fn placeholders() {
+        let mut sets = settings(&[("HDD_1", "%DISTRI%-%VERSION%-%ARCH%.qcow2"),
+                                  ("DISTRI", "sle"),
+                                  ("VERSION", "%MAJOR%-SP1"),
+                                  ("MAJOR", "15"),
+                                  ("URL", "http://x/%20y/%UNKNOWN%"),
+                                  ("PERCENT", "100%")]);
+
+        expand_placeholders(&mut sets).unwrap();
+        assert_eq!(Some("sle-15-SP1-%ARCH%.qcow2"), sets.get("HDD_1"));
+        assert_eq!(Some("http://x/%20y/%UNKNOWN%"), sets.get("URL"));
+        assert_eq!(Some("100%"), sets.get("PERCENT"));
+
+        let mut sets = settings(&[("A", "%B%"), ("B", "x%A%")]);
+        assert!(expand_placeholders(&mut sets).is_err());
+    }
+
+    #[test]
+    fn precedence() {
+        let product = Product {
+            id: 1,
+            arch: "x86_64".to_string(),
+            distri: "SLE".to_string(),
+            flavor: "Server-DVD".to_string(),
+            version: "15".to_string(),
+            settings: settings(&[("QEMURAM", "1024"), ("WORKER_CLASS", "qemu_x86_64")]),
+        };
+        let machine = Machine {
+            id: 2,
+            name: "64bit".to_string(),
+            backend: "qemu".to_string(),
+            settings: settings(&[("QEMURAM", "2048"), ("QEMUCPUS", "2"),
+                                 ("WORKER_CLASS", "tap,qemu_x86_64")]),
+        };
+        let test_suite = TestSuite {
+            description: String::new(),
+            id: 3,
+            name: "ltp_syscalls".to_string(),
+            settings: settings(&[("QEMUCPUS", "4"), ("HDD_1", "%DISTRI%-%VERSION%.qcow2")]),
+        };
+        let template = JobTemplateInfo {
+            group_name: "Kernel".to_string(),
+            id: 4,
+            machine: Machine {
+                id: 2,
+                name: "64bit".to_string(),
+                backend: String::new(),
+                settings: Settings::new(),
+            },
+            prio: 50,
+            product: product.clone(),
+            test_suite: TestSuite {
+                description: String::new(),
+                id: 3,
+                name: "ltp_syscalls".to_string(),
+                settings: Settings::new(),
+            },
+            settings: settings(&[("QEMUCPUS", "8")]),
+        };
+        let args = settings(&[("version", "15-SP1"), ("BUILD", "42")]);
+
+        let res = resolve_settings(&template, &product, &machine, &test_suite, &args).unwrap();
+        let keys: Vec<_> = res.iter().map(|s| s.key.as_str()).collect();
+        assert_eq!(vec!["ARCH", "BACKEND", "BUILD", "DISTRI", "FLAVOR", "HDD_1", "MACHINE",
+                        "QEMUCPUS", "QEMURAM", "TEST", "VERSION", "WORKER_CLASS"], keys);
+        assert_eq!(Some("2048"), res.get("QEMURAM"));
+        assert_eq!(Some("8"), res.get("QEMUCPUS"));
+        assert_eq!(Some("15-SP1"), res.get("VERSION"));
+        assert_eq!(Some("sle"), res.get("DISTRI"));
+        assert_eq!(Some("sle-15-SP1.qcow2"), res.get("HDD_1"));
+        assert_eq!(Some("qemu_x86_64,tap"), res.get("WORKER_CLASS"));
+        assert_eq!(Some("ltp_syscalls"), res.get("TEST"));
+    }
+}
@@ -0,0 +1,270 @@
+//! Export job results as JUnit XML or TAP for CI dashboards
+//!
+//! Each job becomes a test suite and each of its test modules a test case.
+//! Failed modules carry the titles and output of their failed steps along
+//! with links back to openQA. `base` is the openQA URL without the API
+//! path, e.g. `https://openqa.opensuse.org`.
+
+use std::fmt::Write;
+
+use overview::Verdict;
+use {JobDetails, Scenario, TestModule};
+
+fn job_url(base: &str, id: i32) -> String {
+    format!("{}/tests/{}", base.trim_end_matches('/'), id)
+}
+
+/// A link to the module's first step, or the `n`th
+fn module_url(base: &str, id: i32, module: &str, n: usize) -> String {
+    format!("{}#step/{}/{}", job_url(base, id), module, n.max(1))
+}
+
+/// The failed steps of a module, one per line with a link to each
+fn failure_text(base: &str, id: i32, module: &TestModule) -> String {
+    let mut text = String::new();
+
+    for (i, step) in module.details.iter().enumerate().filter(|(_, s)| s.result == "fail") {
+        let what = step.title.as_ref().or(step.needle.as_ref())
+            .map(|s| s.as_str())
+            .unwrap_or("Screenshot");
+        let url = module_url(base, id, &module.name, i + 1);
+
+        let _ = writeln!(text, "Step {}: {} {}", i + 1, what, url);
+        if let Some(ref data) = step.text_data {
+            let _ = writeln!(text, "{}", data.trim_end());
+        }
+    }
+    if text.is_empty() {
+        text = format!("{}\n", module_url(base, id, &module.name, 1));
+    }
+    text
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Not allowed in XML 1.0, even escaped
+            c if c < ' ' && c != '\n' && c != '\r' && c != '\t' => out.push('\u{fffd}'),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Counts for a test suite's attributes
+#[derive(Default)]
+struct Counts {
+    tests: usize,
+    failures: usize,
+    errors: usize,
+    skipped: usize,
+}
+
+impl Counts {
+    fn of(job: &JobDetails) -> Counts {
+        let mut c = Counts { tests: job.testresults.len(), ..Counts::default() };
+
+        for m in &job.testresults {
+            match Verdict::of_module(m) {
+                Verdict::Failed | Verdict::Incomplete => c.failures += 1,
+                Verdict::Aborted | Verdict::Pending => c.skipped += 1,
+                Verdict::Passed | Verdict::Softfailed => (),
+            }
+        }
+        if Verdict::of(&job.job) == Verdict::Incomplete {
+            c.tests += 1;
+            c.errors += 1;
+        }
+        c
+    }
+
+    fn attrs(&self) -> String {
+        format!(r#"tests="{}" failures="{}" errors="{}" skipped="{}""#,
+                self.tests, self.failures, self.errors, self.skipped)
+    }
+}
+
+/// A JUnit XML document with a test suite for each job
+pub fn junit(base: &str, jobs: &[JobDetails]) -> String {
+    let mut total = Counts::default();
+    let mut suites = String::new();
+
+    for job in jobs {
+        let j = &job.job;
+        let counts = Counts::of(job);
+        let class = escape(&Scenario::from(j).to_string());
+        let url = job_url(base, j.id);
+
+        total.tests += counts.tests;
+        total.failures += counts.failures;
+        total.errors += counts.errors;
+        total.skipped += counts.skipped;
+
+        let _ = write!(suites, r#"  <testsuite name="{}" id="{}" {}"#,
+                       escape(&j.name), j.id, counts.attrs());
+        if let Some(ref t) = j.t_started {
+            let _ = write!(suites, r#" timestamp="{}""#, escape(t));
+        }
+        let _ = writeln!(suites, ">");
+        let _ = writeln!(suites, "    <properties>");
+        let _ = writeln!(suites, r#"      <property name="url" value="{}"/>"#, escape(&url));
+        let _ = writeln!(suites, r#"      <property name="result" value="{}"/>"#,
+                         escape(&j.result));
+        let _ = writeln!(suites, "    </properties>");
+
+        for m in &job.testresults {
+            let _ = write!(suites, r#"    <testcase name="{}" classname="{}""#,
+                           escape(&m.name), class);
+            let out = escape(&module_url(base, j.id, &m.name, 1));
+            match Verdict::of_module(m) {
+                Verdict::Passed => {
+                    let _ = writeln!(suites, "/>");
+                    continue;
+                },
+                Verdict::Softfailed => {
+                    let _ = writeln!(suites, ">\n      <system-out>softfailed {}</system-out>",
+                                     out);
+                },
+                Verdict::Failed | Verdict::Incomplete => {
+                    let _ = writeln!(suites, r#">
+      <failure message="{} {}" type="{}">{}</failure>"#,
+                                     escape(&m.name), escape(&m.result), escape(&m.result),
+                                     escape(&failure_text(base, j.id, m)));
+                },
+                Verdict::Aborted | Verdict::Pending => {
+                    let _ = writeln!(suites, ">\n      <skipped message=\"{}\"/>",
+                                     escape(&m.result));
+                },
+            }
+            let _ = writeln!(suites, "    </testcase>");
+        }
+        if counts.errors > 0 {
+            let _ = writeln!(suites, r#"    <testcase name="job" classname="{}">"#, class);
+            let _ = writeln!(suites, r#"      <error message="Job {}" type="{}">{}</error>"#,
+                             escape(&j.result), escape(&j.result), escape(&url));
+            let _ = writeln!(suites, "    </testcase>");
+        }
+        let _ = writeln!(suites, "  </testsuite>");
+    }
+
+    format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuites name=\"openQA\" {}>\n{}</testsuites>\n",
+            total.attrs(), suites)
+}
+
+/// A TAP version 13 stream with a test for each module of each job
+pub fn tap(base: &str, jobs: &[JobDetails]) -> String {
+    let incomplete = |j: &JobDetails| Verdict::of(&j.job) == Verdict::Incomplete;
+    let total: usize = jobs.iter()
+        .map(|j| j.testresults.len() + if incomplete(j) { 1 } else { 0 })
+        .sum();
+    let mut out = format!("TAP version 13\n1..{}\n", total);
+    let mut n = 0;
+
+    for job in jobs {
+        let j = &job.job;
+        let _ = writeln!(out, "# {} {} {}", j.name, j.result, job_url(base, j.id));
+
+        for m in &job.testresults {
+            n += 1;
+            let name = format!("{} {}", j.name, m.name);
+            match Verdict::of_module(m) {
+                Verdict::Passed => {
+                    let _ = writeln!(out, "ok {} - {}", n, name);
+                },
+                Verdict::Softfailed => {
+                    let _ = writeln!(out, "ok {} - {} # softfailed", n, name);
+                },
+                Verdict::Aborted | Verdict::Pending => {
+                    let _ = writeln!(out, "ok {} - {} # SKIP {}", n, name, m.result);
+                },
+                Verdict::Failed | Verdict::Incomplete => {
+                    let _ = writeln!(out, "not ok {} - {}", n, name);
+                    let _ = writeln!(out, "  ---\n  url: {}\n  message: |",
+                                     module_url(base, j.id, &m.name, 1));
+                    for line in failure_text(base, j.id, m).lines() {
+                        let _ = writeln!(out, "    {}", line);
+                    }
+                    let _ = writeln!(out, "  ...");
+                },
+            }
+        }
+        if incomplete(job) {
+            n += 1;
+            let _ = writeln!(out, "not ok {} - {} {}", n, j.name, j.result);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn details() -> Vec<JobDetails> {
+        let text = r#"[{
+            "id": 7, "name": "opensuse-Tumbleweed-DVD-x86_64-Build1-kde@64bit", "test": "kde",
+            "state": "done", "result": "failed", "t_started": "2024-01-02T10:00:00",
+            "settings": {"DISTRI": "opensuse", "VERSION": "Tumbleweed", "FLAVOR": "DVD",
+                         "ARCH": "x86_64", "MACHINE": "64bit"},
+            "testresults": [
+                {"name": "boot", "category": "installation", "result": "passed", "details": []},
+                {"name": "firefox", "category": "x11", "result": "failed", "details": [
+                    {"result": "ok", "title": "wait_serial"},
+                    {"result": "fail", "needle": "firefox-<home>"},
+                    {"result": "fail", "title": "Script", "text_data": "exit 1\n"}
+                ]},
+                {"name": "gimp", "category": "x11", "result": "softfailed"},
+                {"name": "shutdown", "category": "x11", "result": "none"}
+            ]
+        }, {
+            "id": 8, "name": "textmode", "state": "done", "result": "incomplete",
+            "testresults": []
+        }]"#;
+        serde_json::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn junit_xml() {
+        let xml = junit("https://o3/", &details());
+
+        assert!(xml.contains(r#"<testsuites name="openQA" tests="5" failures="1" errors="1" skipped="1">"#));
+        assert!(xml.contains(r#"<testsuite name="opensuse-Tumbleweed-DVD-x86_64-Build1-kde@64bit" id="7" tests="4" failures="1" errors="0" skipped="1" timestamp="2024-01-02T10:00:00">"#));
+        assert!(xml.contains(r#"<testcase name="boot" classname="opensuse-Tumbleweed-DVD-x86_64-kde@64bit"/>"#));
+        assert!(xml.contains("Step 2: firefox-&lt;home&gt; https://o3/tests/7#step/firefox/2\nStep 3: Script https://o3/tests/7#step/firefox/3\nexit 1\n</failure>"));
+        assert!(xml.contains("<system-out>softfailed https://o3/tests/7#step/gimp/1</system-out>"));
+        assert!(xml.contains(r#"<skipped message="none"/>"#));
+        assert!(xml.contains(r#"<error message="Job incomplete" type="incomplete">https://o3/tests/8</error>"#));
+    }
+
+    #[test]
+    fn tap_stream() {
+        let tap = tap("https://o3", &details());
+        let lines: Vec<&str> = tap.lines().collect();
+
+        assert_eq!(&["TAP version 13", "1..5",
+                     "# opensuse-Tumbleweed-DVD-x86_64-Build1-kde@64bit failed https://o3/tests/7",
+                     "ok 1 - opensuse-Tumbleweed-DVD-x86_64-Build1-kde@64bit boot",
+                     "not ok 2 - opensuse-Tumbleweed-DVD-x86_64-Build1-kde@64bit firefox",
+                     "  ---",
+                     "  url: https://o3/tests/7#step/firefox/1",
+                     "  message: |",
+                     "    Step 2: firefox-<home> https://o3/tests/7#step/firefox/2",
+                     "    Step 3: Script https://o3/tests/7#step/firefox/3",
+                     "    exit 1",
+                     "  ...",
+                     "ok 3 - opensuse-Tumbleweed-DVD-x86_64-Build1-kde@64bit gimp # softfailed",
+                     "ok 4 - opensuse-Tumbleweed-DVD-x86_64-Build1-kde@64bit shutdown # SKIP none",
+                     "# textmode incomplete https://o3/tests/8",
+                     "not ok 5 - textmode incomplete"][..],
+                   &lines[..]);
+    }
+}
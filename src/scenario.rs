@@ -0,0 +1,187 @@
+//! The identity of a test across builds
+//!
+//! openQA calls the combination of product, test suite and machine a
+//! scenario and shows it as `distri-version-flavor-arch-test@machine`. Each
+//! build schedules a job for every scenario in its job groups, so a
+//! scenario's jobs form its history.
+
+use std::fmt;
+use std::str::FromStr;
+
+use failure::Error;
+
+use ids::ProductKey;
+use {Job, JobTemplateInfo};
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Scenario {
+    pub distri: String,
+    pub version: String,
+    pub flavor: String,
+    pub arch: String,
+    pub test: String,
+    pub machine: String,
+}
+
+impl Scenario {
+    pub fn new<T, M>(product: ProductKey, test: T, machine: M) -> Scenario
+    where
+        T: Into<String>,
+        M: Into<String>,
+    {
+        Scenario {
+            distri: product.distri,
+            version: product.version,
+            flavor: product.flavor,
+            arch: product.arch,
+            test: test.into(),
+            machine: machine.into(),
+        }
+    }
+
+    pub fn product(&self) -> ProductKey {
+        ProductKey::new(self.distri.as_str(), self.version.as_str(),
+                        self.flavor.as_str(), self.arch.as_str())
+    }
+
+    /// The job query parameters which select this scenario
+    pub fn query(&self) -> [(&'static str, &str); 6] {
+        [("distri", &self.distri), ("version", &self.version), ("flavor", &self.flavor),
+         ("arch", &self.arch), ("test", &self.test), ("machine", &self.machine)]
+    }
+}
+
+impl<'a> From<&'a Job> for Scenario {
+    fn from(job: &'a Job) -> Scenario {
+        let setting = |k: &str| job.settings.get(k).cloned().unwrap_or_default();
+        let test = if job.test.is_empty() { setting("TEST") } else { job.test.clone() };
+
+        Scenario {
+            distri: setting("DISTRI"),
+            version: setting("VERSION"),
+            flavor: setting("FLAVOR"),
+            arch: setting("ARCH"),
+            test,
+            machine: setting("MACHINE"),
+        }
+    }
+}
+
+impl<'a> From<&'a JobTemplateInfo> for Scenario {
+    fn from(t: &'a JobTemplateInfo) -> Scenario {
+        Scenario::new(ProductKey::from(&t.product), t.test_suite.name.as_str(),
+                      t.machine.name.as_str())
+    }
+}
+
+impl fmt::Display for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}-{}-{}-{}@{}", self.distri, self.version, self.flavor, self.arch,
+               self.test, self.machine)
+    }
+}
+
+/// Parse `distri-version-flavor-arch-test@machine`
+///
+/// Only the version may contain `-`, as in `15-SP5`, otherwise the name is
+/// ambiguous. Scenarios with a dash in the flavor or test must be built
+/// with `Scenario::new` instead.
+impl FromStr for Scenario {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Scenario, Error> {
+        let (name, machine) = match s.rfind('@') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => bail!("Scenario '{}' has no @machine", s),
+        };
+        let mut back = name.rsplitn(4, '-');
+        let (test, arch, flavor) = (back.next(), back.next(), back.next());
+        let mut front = back.next().unwrap_or_default().splitn(2, '-');
+        let (distri, version) = (front.next(), front.next());
+
+        match (distri, version, flavor, arch, test) {
+            (Some(d), Some(v), Some(f), Some(a), Some(t))
+                if ![d, v, f, a, t, machine].iter().any(|p| p.is_empty()) =>
+            {
+                Ok(Scenario::new(ProductKey::new(d, v, f, a), t, machine))
+            },
+            _ => bail!("Scenario '{}' is not distri-version-flavor-arch-test@machine", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {Machine, Product, Settings, TestSuite};
+
+    #[test]
+    fn parse_format() {
+        let s: Scenario = "sle-15-SP5-Online-x86_64-textmode@64bit".parse().unwrap();
+
+        assert_eq!("sle", s.distri);
+        assert_eq!("15-SP5", s.version);
+        assert_eq!("Online", s.flavor);
+        assert_eq!("x86_64", s.arch);
+        assert_eq!("textmode", s.test);
+        assert_eq!("64bit", s.machine);
+        assert_eq!("sle-15-SP5-Online-x86_64-textmode@64bit", s.to_string());
+        assert_eq!("sle-15-SP5-Online-x86_64", s.product().to_string());
+
+        assert!("opensuse-Tumbleweed-DVD-x86_64-kde".parse::<Scenario>().is_err());
+        assert!("opensuse-DVD-x86_64-kde@64bit".parse::<Scenario>().is_err());
+        assert!("opensuse--DVD-x86_64-kde@64bit".parse::<Scenario>().is_err());
+    }
+
+    #[test]
+    fn from_template() {
+        let t = JobTemplateInfo {
+            group_name: "Tumbleweed".to_string(),
+            id: 1,
+            machine: Machine {
+                id: 1,
+                name: "uefi".to_string(),
+                backend: "qemu".to_string(),
+                settings: Settings::new(),
+            },
+            prio: 50,
+            product: Product {
+                id: 1,
+                distri: "opensuse".to_string(),
+                version: "Tumbleweed".to_string(),
+                flavor: "DVD".to_string(),
+                arch: "x86_64".to_string(),
+                settings: Settings::new(),
+            },
+            test_suite: TestSuite {
+                id: 1,
+                name: "kde".to_string(),
+                description: String::new(),
+                settings: Settings::new(),
+            },
+            settings: Settings::new(),
+        };
+
+        assert_eq!("opensuse-Tumbleweed-DVD-x86_64-kde@uefi", Scenario::from(&t).to_string());
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn history() {
+        use mock::MockServer;
+
+        let mut server = MockServer::start().unwrap();
+        let oqa = server.client();
+        let scenario: Scenario = "opensuse-Tumbleweed-DVD-x86_64-kde@64bit".parse().unwrap();
+
+        for id in 1..8 {
+            let test = if id % 2 == 0 { "kde" } else { "gnome" };
+            server.add_job(Job::fake(id, test));
+        }
+
+        let jobs = server.block_on(oqa.get_scenario_history(&scenario, 2)).unwrap();
+        assert_eq!(vec![6, 4], jobs.iter().map(|j| j.id).collect::<Vec<_>>());
+        assert_eq!(Some("64bit"), server.received()[0].param("machine"));
+        assert_eq!(1, server.received().len());
+    }
+}
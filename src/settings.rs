@@ -0,0 +1,197 @@
+use std::iter::FromIterator;
+use std::ops::Deref;
+use std::slice;
+use std::vec;
+
+use failure::Error;
+
+use Setting;
+
+/// An ordered list of settings with map like access
+///
+/// Keys are compared case insensitively as openQA upper cases setting keys.
+/// This serializes to the same list of key/value objects used by the API.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Settings(Vec<Setting>);
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn position(&self, key: &str) -> Option<usize> {
+        self.0.iter().position(|s| s.key.eq_ignore_ascii_case(key))
+    }
+
+    pub fn get_setting(&self, key: &str) -> Option<&Setting> {
+        self.position(key).map(|i| &self.0[i])
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_setting(key).map(|s| s.value.as_str())
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.position(key).is_some()
+    }
+
+    /// Set the value of `key`, returning the previous value if there was one
+    ///
+    /// Existing settings keep their position and spelling, new ones are
+    /// appended.
+    pub fn set<K, V>(&mut self, key: K, value: V) -> Option<String>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let key = key.into();
+        let value = value.into();
+
+        match self.position(&key) {
+            Some(i) => Some(::std::mem::replace(&mut self.0[i].value, value)),
+            None => {
+                self.0.push(Setting { key, value });
+                None
+            },
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.position(key).map(|i| self.0.remove(i).value)
+    }
+
+    /// Interpret the value like openQA's Perl code does: empty and "0" are
+    /// false, anything else is true
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key).map(|v| !(v.is_empty() || v == "0"))
+    }
+
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>, Error> {
+        match self.get(key) {
+            Some(v) => v.trim().parse().map(Some).map_err(|e| {
+                format_err!("Setting {}={} is not an integer: {}", key, v, e)
+            }),
+            None => Ok(None),
+        }
+    }
+
+    /// Split a comma separated value, ignoring empty items
+    pub fn get_list(&self, key: &str) -> Option<Vec<&str>> {
+        self.get(key).map(|v| {
+            v.split(',').map(|i| i.trim()).filter(|i| !i.is_empty()).collect()
+        })
+    }
+
+    pub fn into_vec(self) -> Vec<Setting> {
+        self.0
+    }
+}
+
+impl Deref for Settings {
+    type Target = [Setting];
+
+    fn deref(&self) -> &[Setting] {
+        &self.0
+    }
+}
+
+impl From<Vec<Setting>> for Settings {
+    fn from(settings: Vec<Setting>) -> Settings {
+        Settings(settings)
+    }
+}
+
+impl FromIterator<Setting> for Settings {
+    fn from_iter<I: IntoIterator<Item=Setting>>(iter: I) -> Settings {
+        Settings(iter.into_iter().collect())
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for Settings
+where
+    K: Into<String>,
+    V: Into<String>,
+{
+    fn from_iter<I: IntoIterator<Item=(K, V)>>(iter: I) -> Settings {
+        let mut settings = Settings::new();
+        for (k, v) in iter {
+            settings.set(k, v);
+        }
+        settings
+    }
+}
+
+impl IntoIterator for Settings {
+    type Item = Setting;
+    type IntoIter = vec::IntoIter<Setting>;
+
+    fn into_iter(self) -> vec::IntoIter<Setting> {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Settings {
+    type Item = &'a Setting;
+    type IntoIter = slice::Iter<'a, Setting>;
+
+    fn into_iter(self) -> slice::Iter<'a, Setting> {
+        self.0.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn map_access() {
+        let mut sets: Settings = vec![("HDD_1", "a.qcow2"), ("QEMUCPUS", "2")]
+            .into_iter().collect();
+
+        assert_eq!(Some("a.qcow2"), sets.get("hdd_1"));
+        assert!(sets.contains("QemuCpus"));
+        assert_eq!(Some("a.qcow2".to_string()), sets.set("hdd_1", "b.qcow2"));
+        assert_eq!("HDD_1", sets[0].key);
+        assert_eq!(None, sets.set("DESKTOP", "textmode"));
+        assert_eq!(Some("2".to_string()), sets.remove("QEMUCPUS"));
+        assert_eq!(None, sets.remove("QEMUCPUS"));
+
+        let keys: Vec<&str> = sets.iter().map(|s| s.key.as_str()).collect();
+        assert_eq!(vec!["HDD_1", "DESKTOP"], keys);
+    }
+
+    #[test]
+    fn typed() {
+        let sets: Settings = vec![("A", "1"), ("B", "0"), ("C", ""), ("D", " 42 "),
+                                  ("E", "x"), ("F", "a, b,,c")]
+            .into_iter().collect();
+
+        assert_eq!(Some(true), sets.get_bool("A"));
+        assert_eq!(Some(false), sets.get_bool("B"));
+        assert_eq!(Some(false), sets.get_bool("C"));
+        assert_eq!(None, sets.get_bool("Z"));
+        assert_eq!(Some(42), sets.get_int("D").unwrap());
+        assert!(sets.get_int("E").is_err());
+        assert_eq!(None, sets.get_int("Z").unwrap());
+        assert_eq!(Some(vec!["a", "b", "c"]), sets.get_list("F"));
+    }
+
+    #[test]
+    fn serde() {
+        let json = r#"[{"key":"HDD_1","value":"a.qcow2"},{"key":"BOOT","value":"1"}]"#;
+        let sets: Settings = serde_json::from_str(json).unwrap();
+
+        assert_eq!(Some("1"), sets.get("BOOT"));
+        assert_eq!(json, serde_json::to_string(&sets).unwrap());
+    }
+}
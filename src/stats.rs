@@ -0,0 +1,245 @@
+//! Find tests which fail intermittently
+//!
+//! Given the history of some scenarios, e.g. from `get_scenario_history`
+//! and `get_job_details`, this counts the results of each scenario and of
+//! each test module within it. Aborted and unfinished runs are ignored.
+//!
+//! The flakiness score is how often the result flipped between passing and
+//! failing from one run to the next. A test which always fails scores 0,
+//! one which alternates scores 1.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use overview::{self, Verdict};
+use {Job, JobDetails, Scenario};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub runs: usize,
+    pub passed: usize,
+    pub softfailed: usize,
+    /// Failed or incomplete
+    pub failed: usize,
+    /// The latest verdict and how many runs in a row ended with it
+    pub streak: Option<(Verdict, usize)>,
+    pub longest_failure_streak: usize,
+    /// How many times the result changed between passing and failing
+    pub flips: usize,
+}
+
+impl Stats {
+    /// Count verdicts given from oldest to newest
+    pub fn new<I: IntoIterator<Item=Verdict>>(verdicts: I) -> Stats {
+        let mut stats = Stats::default();
+        let mut failing = 0;
+
+        for v in verdicts {
+            match v {
+                Verdict::Passed => stats.passed += 1,
+                Verdict::Softfailed => stats.softfailed += 1,
+                Verdict::Failed | Verdict::Incomplete => stats.failed += 1,
+                Verdict::Aborted | Verdict::Pending => continue,
+            }
+            stats.runs += 1;
+
+            failing = if v.is_failure() { failing + 1 } else { 0 };
+            stats.longest_failure_streak = stats.longest_failure_streak.max(failing);
+            if let Some((last, _)) = stats.streak {
+                if last.is_failure() != v.is_failure() {
+                    stats.flips += 1;
+                }
+            }
+            stats.streak = match stats.streak {
+                Some((last, n)) if last == v => Some((v, n + 1)),
+                _ => Some((v, 1)),
+            };
+        }
+        stats
+    }
+
+    fn rate(&self, n: usize) -> f64 {
+        if self.runs == 0 { 0.0 } else { n as f64 / self.runs as f64 }
+    }
+
+    pub fn pass_rate(&self) -> f64 {
+        self.rate(self.passed)
+    }
+
+    pub fn softfail_rate(&self) -> f64 {
+        self.rate(self.softfailed)
+    }
+
+    pub fn fail_rate(&self) -> f64 {
+        self.rate(self.failed)
+    }
+
+    /// Flips per pair of consecutive runs, from 0 to 1
+    pub fn flakiness(&self) -> f64 {
+        if self.runs < 2 { 0.0 } else { self.flips as f64 / (self.runs - 1) as f64 }
+    }
+}
+
+/// Each scenario's jobs, oldest first
+fn oldest_first<'a, I>(jobs: I) -> BTreeMap<Scenario, Vec<&'a Job>>
+where
+    I: IntoIterator<Item=&'a Job>,
+{
+    let mut scenarios = overview::by_scenario(jobs);
+
+    for jobs in scenarios.values_mut() {
+        jobs.sort_by_key(|j| j.id);
+    }
+    scenarios
+}
+
+/// The stats of each scenario
+pub fn scenario_stats<'a, I>(jobs: I) -> BTreeMap<Scenario, Stats>
+where
+    I: IntoIterator<Item=&'a Job>,
+{
+    oldest_first(jobs).into_iter()
+        .map(|(s, jobs)| (s, Stats::new(jobs.into_iter().map(Verdict::of))))
+        .collect()
+}
+
+/// The stats of each test module in each scenario
+pub fn module_stats(jobs: &[JobDetails]) -> BTreeMap<(Scenario, String), Stats> {
+    let details: BTreeMap<i32, &JobDetails> = jobs.iter().map(|j| (j.job.id, j)).collect();
+    let mut verdicts: BTreeMap<(Scenario, String), Vec<Verdict>> = BTreeMap::new();
+
+    for (scenario, jobs) in oldest_first(jobs.iter().map(|j| &j.job)) {
+        for job in jobs {
+            for module in &details[&job.id].testresults {
+                verdicts.entry((scenario.clone(), module.name.clone()))
+                    .or_default()
+                    .push(Verdict::of_module(module));
+            }
+        }
+    }
+    verdicts.into_iter().map(|(k, v)| (k, Stats::new(v))).collect()
+}
+
+/// A scenario, or a module of it, and its stats
+#[derive(Clone, Debug, Serialize)]
+pub struct Entry {
+    pub scenario: Scenario,
+    pub module: Option<String>,
+    pub stats: Stats,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = &self.stats;
+        let streak = s.streak.map(|(v, n)| format!("{} {:?}", n, v)).unwrap_or_default();
+
+        write!(f, "{:5.2} {:4} {:4.0}% {:4.0}% {:4.0}% {:<14} {}",
+               s.flakiness(), s.runs, s.pass_rate() * 100.0, s.softfail_rate() * 100.0,
+               s.fail_rate() * 100.0, streak, self.scenario)?;
+        if let Some(ref m) = self.module {
+            write!(f, " {}", m)?;
+        }
+        Ok(())
+    }
+}
+
+/// Scenarios and modules, the flakiest first
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Report {
+    pub entries: Vec<Entry>,
+}
+
+impl Report {
+    /// Rank everything which ran at least `min_runs` times
+    pub fn new(jobs: &[JobDetails], min_runs: usize) -> Report {
+        let scenarios = scenario_stats(jobs.iter().map(|j| &j.job)).into_iter()
+            .map(|(scenario, stats)| Entry { scenario, module: None, stats });
+        let modules = module_stats(jobs).into_iter()
+            .map(|((scenario, module), stats)| Entry { scenario, module: Some(module), stats });
+        let mut entries: Vec<Entry> = scenarios.chain(modules)
+            .filter(|e| e.stats.runs >= min_runs)
+            .collect();
+
+        entries.sort_by(|a, b| {
+            let key = |e: &Entry| (e.stats.flakiness(), e.stats.fail_rate());
+            let (ka, kb) = (key(a), key(b));
+            kb.partial_cmp(&ka).unwrap_or(Ordering::Equal)
+                .then_with(|| (&a.scenario, &a.module).cmp(&(&b.scenario, &b.module)))
+        });
+        Report { entries }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "FLAKY RUNS  PASS  SOFT  FAIL STREAK         SCENARIO [MODULE]")?;
+        for e in &self.entries {
+            writeln!(f, "{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TestModule;
+    use overview::Verdict::*;
+
+    #[test]
+    fn streaks_and_flips() {
+        let s = Stats::new(vec![Passed, Failed, Aborted, Failed, Incomplete, Softfailed, Passed]);
+
+        assert_eq!(6, s.runs);
+        assert_eq!((2, 1, 3), (s.passed, s.softfailed, s.failed));
+        assert_eq!(Some((Passed, 1)), s.streak);
+        assert_eq!(3, s.longest_failure_streak);
+        assert_eq!(2, s.flips);
+        assert!((s.flakiness() - 0.4).abs() < 1e-9);
+        assert!((s.fail_rate() - 0.5).abs() < 1e-9);
+
+        assert_eq!(Some((Failed, 2)), Stats::new(vec![Incomplete, Failed, Failed]).streak);
+        assert_eq!(0.0, Stats::new(vec![Failed; 5]).flakiness());
+        assert_eq!(1.0, Stats::new(vec![Passed, Failed, Passed, Failed]).flakiness());
+        assert_eq!(0.0, Stats::new(vec![Pending]).flakiness());
+    }
+
+    fn details(id: i32, test: &str, modules: &[(&str, &str)]) -> JobDetails {
+        let testresults: Vec<TestModule> = modules.iter().map(|(n, r)| TestModule {
+            name: n.to_string(),
+            category: String::new(),
+            result: r.to_string(),
+            details: Vec::new(),
+        }).collect();
+        let failed = testresults.iter().any(|m| m.result == "failed");
+        let job = Job::fake(id, test).with_result(if failed { "failed" } else { "passed" });
+        JobDetails { job, testresults }
+    }
+
+    #[test]
+    fn ranked_report() {
+        let jobs = vec![
+            details(1, "kde", &[("boot", "passed"), ("firefox", "failed")]),
+            details(2, "kde", &[("boot", "passed"), ("firefox", "passed")]),
+            details(3, "kde", &[("boot", "passed"), ("firefox", "failed")]),
+            details(4, "gnome", &[("boot", "passed"), ("nautilus", "failed")]),
+            details(5, "gnome", &[("boot", "passed"), ("nautilus", "failed")]),
+            details(6, "gnome", &[("boot", "passed"), ("nautilus", "failed")]),
+            details(7, "xfce", &[("boot", "passed")]),
+        ];
+        let report = Report::new(&jobs, 2);
+        let names: Vec<String> = report.entries.iter()
+            .map(|e| format!("{} {}", e.scenario.test, e.module.as_deref().unwrap_or("-")))
+            .collect();
+
+        assert_eq!(vec!["kde -", "kde firefox", "gnome -", "gnome nautilus",
+                        "gnome boot", "kde boot"], names);
+        assert_eq!(3, report.entries[3].stats.longest_failure_streak);
+
+        let text = report.to_string();
+        assert_eq!(7, text.lines().count());
+        assert_eq!(" 1.00    3   33%    0%   67% 1 Failed       opensuse-Tumbleweed-DVD-x86_64-kde@64bit",
+                   text.lines().nth(1).unwrap());
+    }
+}
@@ -0,0 +1,516 @@
+//! Reconcile openQA's machines, products, test suites and job templates with
+//! a desired state kept in files
+//!
+//! The desired state uses the same shape as the API's list responses (and
+//! openQA's `load_templates` format), but ids may be left out. Objects are
+//! matched by name; products by distri, version, flavor and arch. A `Plan`
+//! can be printed as a dry run before it is applied.
+//!
+//! Job groups are created and updated, but never deleted, and their parent
+//! groups are not managed.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use futures::{stream, Future, Stream};
+use failure::Error;
+use serde::{Serialize, Serializer};
+use serde_json::{self, Value};
+use serde_yaml;
+
+use lint;
+use {JobGroup, JobTemplateInfo, Machine, OpenQA, Product, Settings, TestSuite};
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct State {
+    #[serde(rename = "JobGroups", alias = "job_groups", default,
+            serialize_with = "dump_job_groups")]
+    pub job_groups: Vec<JobGroup>,
+    #[serde(rename = "Machines", alias = "machines", default)]
+    pub machines: Vec<Machine>,
+    #[serde(rename = "Products", alias = "products", default)]
+    pub products: Vec<Product>,
+    #[serde(rename = "TestSuites", alias = "test_suites", default)]
+    pub test_suites: Vec<TestSuite>,
+    #[serde(rename = "JobTemplates", alias = "job_templates", default)]
+    pub job_templates: Vec<JobTemplateInfo>,
+}
+
+/// Write job groups like `dump_templates`, which names them `group_name`
+fn dump_job_groups<S: Serializer>(groups: &[JobGroup], s: S) -> Result<S::Ok, S::Error> {
+    let groups: Vec<Value> = groups.iter().map(|g| {
+        let mut v = serde_json::to_value(g).unwrap_or_default();
+        if let Some(name) = v.as_object_mut().and_then(|o| o.remove("name")) {
+            v["group_name"] = name;
+        }
+        v
+    }).collect();
+    groups.serialize(s)
+}
+
+impl State {
+    /// Fetch the live state from openQA
+    pub fn fetch(oqa: &OpenQA) -> impl Future<Item=State, Error=Error> {
+        oqa.get_machines()
+            .join5(oqa.get_products(), oqa.get_test_suites(), oqa.get_job_templates(),
+                   oqa.get_job_groups())
+            .map(|(m, p, t, j, g)| State {
+                job_groups: g,
+                machines: m.machines,
+                products: p.products,
+                test_suites: t.test_suites,
+                job_templates: j.job_templates,
+            })
+    }
+
+    pub fn from_json(json: &str) -> Result<State, Error> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<State, Error> {
+        Ok(serde_yaml::from_str(yaml)?)
+    }
+
+    /// Load a state from a YAML (`.yaml` or `.yml`) or JSON file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<State, Error> {
+        let path = path.as_ref();
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format_err!("Reading {}: {}", path.display(), e))?;
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => State::from_yaml(&text),
+            _ => State::from_json(&text),
+        }.map_err(|e| format_err!("Parsing {}: {}", path.display(), e))
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_yaml(&self) -> Result<String, Error> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+
+    /// Save as YAML or JSON depending on the file extension, like `from_file`
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let text = match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => self.to_yaml(),
+            _ => self.to_json(),
+        }?;
+
+        File::create(path)
+            .and_then(|mut f| f.write_all(text.as_bytes()))
+            .map_err(|e| format_err!("Writing {}: {}", path.display(), e))
+    }
+}
+
+fn product_name(p: &Product) -> String {
+    format!("{}-{}-{}-{}", p.distri, p.version, p.flavor, p.arch)
+}
+
+fn same_product(a: &Product, b: &Product) -> bool {
+    a.distri == b.distri && a.version == b.version && a.flavor == b.flavor && a.arch == b.arch
+}
+
+fn template_name(t: &JobTemplateInfo) -> String {
+    format!("{}: {}-{}@{}", t.group_name, product_name(&t.product),
+            t.test_suite.name, t.machine.name)
+}
+
+fn same_template(a: &JobTemplateInfo, b: &JobTemplateInfo) -> bool {
+    a.group_name == b.group_name && same_product(&a.product, &b.product) &&
+        a.machine.name == b.machine.name && a.test_suite.name == b.test_suite.name
+}
+
+fn settings_diff(live: &Settings, desired: &Settings, lines: &mut Vec<String>) {
+    for c in lint::diff(live, desired) {
+        lines.extend(c.to_string().lines().map(|l| l.to_string()));
+    }
+}
+
+fn group_diff(live: &JobGroup, desired: &JobGroup) -> Vec<String> {
+    // The schedule can't be changed through the job groups API
+    let strip = |g: &JobGroup| serde_json::to_value(JobGroup {
+        id: 0,
+        parent_id: None,
+        template: None,
+        ..g.clone()
+    }).unwrap_or_default();
+    let (live, desired) = (strip(live), strip(desired));
+    let (live, desired) = match (live.as_object(), desired.as_object()) {
+        (Some(l), Some(d)) => (l.clone(), d.clone()),
+        _ => return Vec::new(),
+    };
+
+    desired.iter()
+        .filter(|(k, v)| live.get(k.as_str()) != Some(v))
+        .map(|(k, v)| format!("{}: {} -> {}", k, live.get(k.as_str()).unwrap_or(&Value::Null), v))
+        .collect()
+}
+
+fn field_diff(name: &str, live: &str, desired: &str, lines: &mut Vec<String>) {
+    if live != desired {
+        lines.push(format!("{}: {} -> {}", name, live, desired));
+    }
+}
+
+/// A single change to openQA. Updates carry the desired object, with the
+/// live object's id, and a description of what differs.
+#[derive(Debug, Clone)]
+pub enum Op {
+    NewJobGroup(JobGroup),
+    UpdJobGroup(JobGroup, Vec<String>),
+    NewMachine(Machine),
+    UpdMachine(Machine, Vec<String>),
+    DelMachine(Machine),
+    NewProduct(Product),
+    UpdProduct(Product, Vec<String>),
+    DelProduct(Product),
+    NewTestSuite(TestSuite),
+    UpdTestSuite(TestSuite, Vec<String>),
+    DelTestSuite(TestSuite),
+    NewJobTemplate(JobTemplateInfo),
+    UpdJobTemplate(JobTemplateInfo, Vec<String>),
+    DelJobTemplate(JobTemplateInfo),
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (sign, what, name, lines) = match self {
+            Op::NewJobGroup(g) => ('+', "job group", g.name.clone(), None),
+            Op::UpdJobGroup(g, l) => ('~', "job group", g.name.clone(), Some(l)),
+            Op::NewMachine(m) => ('+', "machine", m.name.clone(), None),
+            Op::UpdMachine(m, l) => ('~', "machine", m.name.clone(), Some(l)),
+            Op::DelMachine(m) => ('-', "machine", m.name.clone(), None),
+            Op::NewProduct(p) => ('+', "product", product_name(p), None),
+            Op::UpdProduct(p, l) => ('~', "product", product_name(p), Some(l)),
+            Op::DelProduct(p) => ('-', "product", product_name(p), None),
+            Op::NewTestSuite(t) => ('+', "test suite", t.name.clone(), None),
+            Op::UpdTestSuite(t, l) => ('~', "test suite", t.name.clone(), Some(l)),
+            Op::DelTestSuite(t) => ('-', "test suite", t.name.clone(), None),
+            Op::NewJobTemplate(t) => ('+', "job template", template_name(t), None),
+            Op::UpdJobTemplate(t, l) => ('~', "job template", template_name(t), Some(l)),
+            Op::DelJobTemplate(t) => ('-', "job template", template_name(t), None),
+        };
+
+        write!(f, "{} {} {}", sign, what, name)?;
+        for l in lines.into_iter().flatten() {
+            write!(f, "\n    {}", l)?;
+        }
+        Ok(())
+    }
+}
+
+fn expect_update(r: ::UpdateResult) -> Result<(), Error> {
+    r.into_result().map(|_| ())
+}
+
+fn expect_create(r: ::CreateResult) -> Result<(), Error> {
+    r.into_result().map(|_| ())
+}
+
+impl Op {
+    fn apply(self, oqa: &OpenQA) -> Box<dyn Future<Item=(), Error=Error> + Send> {
+        let desc = self.to_string();
+        let fut: Box<dyn Future<Item=(), Error=Error> + Send> = match self {
+            Op::NewJobGroup(g) => Box::new(oqa.new_job_group(&g).and_then(expect_create)),
+            Op::UpdJobGroup(g, _) => Box::new(oqa.upd_job_group(&g).and_then(expect_create)),
+            Op::NewMachine(m) => Box::new(oqa.new_machine(&m).and_then(expect_create)),
+            Op::UpdMachine(m, _) => Box::new(oqa.upd_machine(&m).and_then(expect_update)),
+            Op::DelMachine(m) => Box::new(oqa.del_machine(m.id).and_then(expect_update)),
+            Op::NewProduct(p) => Box::new(oqa.new_product(&p).and_then(expect_create)),
+            Op::UpdProduct(p, _) => Box::new(oqa.upd_product(&p).and_then(expect_update)),
+            Op::DelProduct(p) => Box::new(oqa.del_product(p.id).and_then(expect_update)),
+            Op::NewTestSuite(t) => Box::new(oqa.new_test_suite(&t).and_then(expect_create)),
+            Op::UpdTestSuite(t, _) => Box::new(oqa.upd_test_suite(&t).and_then(expect_update)),
+            Op::DelTestSuite(t) => Box::new(oqa.del_test_suite(t.id).and_then(expect_update)),
+            Op::NewJobTemplate(t) => {
+                Box::new(oqa.new_job_template_named(&t).and_then(expect_create))
+            },
+            Op::UpdJobTemplate(t, _) => {
+                Box::new(oqa.upd_job_template_prio(&t).and_then(expect_create))
+            },
+            Op::DelJobTemplate(t) => {
+                Box::new(oqa.del_job_template(t.id).and_then(expect_update))
+            },
+        };
+
+        Box::new(fut.map_err(move |e| format_err!("{}: {}", desc, e)))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    pub ops: Vec<Op>,
+}
+
+impl Plan {
+    /// Work out the changes needed to make `live` look like `desired`
+    ///
+    /// Nothing is deleted unless `prune` is set. Even then, only job
+    /// templates in groups mentioned by `desired` are removed, and test
+    /// suites, products and machines only once no remaining template uses
+    /// them. Only the priority of a job template is compared, because the
+    /// API can't set a template's settings.
+    pub fn new(desired: &State, live: &State, prune: bool) -> Plan {
+        let mut ops = Vec::new();
+
+        for d in &desired.job_groups {
+            match live.job_groups.iter().find(|l| l.name == d.name) {
+                None => ops.push(Op::NewJobGroup(JobGroup { parent_id: None, ..d.clone() })),
+                Some(l) => {
+                    let lines = group_diff(l, d);
+                    if !lines.is_empty() {
+                        let g = JobGroup { id: l.id, parent_id: l.parent_id, ..d.clone() };
+                        ops.push(Op::UpdJobGroup(g, lines));
+                    }
+                },
+            }
+        }
+
+        for d in &desired.machines {
+            match live.machines.iter().find(|l| l.name == d.name) {
+                None => ops.push(Op::NewMachine(d.clone())),
+                Some(l) => {
+                    let mut lines = Vec::new();
+                    field_diff("backend", &l.backend, &d.backend, &mut lines);
+                    settings_diff(&l.settings, &d.settings, &mut lines);
+                    if !lines.is_empty() {
+                        ops.push(Op::UpdMachine(Machine { id: l.id, ..d.clone() }, lines));
+                    }
+                },
+            }
+        }
+
+        for d in &desired.products {
+            match live.products.iter().find(|l| same_product(l, d)) {
+                None => ops.push(Op::NewProduct(d.clone())),
+                Some(l) => {
+                    let mut lines = Vec::new();
+                    settings_diff(&l.settings, &d.settings, &mut lines);
+                    if !lines.is_empty() {
+                        ops.push(Op::UpdProduct(Product { id: l.id, ..d.clone() }, lines));
+                    }
+                },
+            }
+        }
+
+        for d in &desired.test_suites {
+            match live.test_suites.iter().find(|l| l.name == d.name) {
+                None => ops.push(Op::NewTestSuite(d.clone())),
+                Some(l) => {
+                    let mut lines = Vec::new();
+                    field_diff("description", &l.description, &d.description, &mut lines);
+                    settings_diff(&l.settings, &d.settings, &mut lines);
+                    if !lines.is_empty() {
+                        ops.push(Op::UpdTestSuite(TestSuite { id: l.id, ..d.clone() }, lines));
+                    }
+                },
+            }
+        }
+
+        let mut kept: Vec<&JobTemplateInfo> = desired.job_templates.iter().collect();
+        for l in &live.job_templates {
+            let managed = desired.job_templates.iter().any(|d| d.group_name == l.group_name);
+            if !managed {
+                kept.push(l);
+            } else if prune && !desired.job_templates.iter().any(|d| same_template(l, d)) {
+                ops.push(Op::DelJobTemplate(l.clone()));
+            }
+        }
+
+        for d in &desired.job_templates {
+            match live.job_templates.iter().find(|l| same_template(l, d)) {
+                None => ops.push(Op::NewJobTemplate(d.clone())),
+                Some(l) => {
+                    let mut lines = Vec::new();
+                    field_diff("prio", &l.prio.to_string(), &d.prio.to_string(), &mut lines);
+                    if !lines.is_empty() {
+                        let t = JobTemplateInfo { id: l.id, ..d.clone() };
+                        ops.push(Op::UpdJobTemplate(t, lines));
+                    }
+                },
+            }
+        }
+
+        if prune {
+            // openQA deletes the templates using these along with them
+            for l in &live.test_suites {
+                if !desired.test_suites.iter().any(|d| d.name == l.name)
+                    && !kept.iter().any(|t| t.test_suite.name == l.name)
+                {
+                    ops.push(Op::DelTestSuite(l.clone()));
+                }
+            }
+            for l in &live.products {
+                if !desired.products.iter().any(|d| same_product(l, d))
+                    && !kept.iter().any(|t| same_product(&t.product, l))
+                {
+                    ops.push(Op::DelProduct(l.clone()));
+                }
+            }
+            for l in &live.machines {
+                if !desired.machines.iter().any(|d| d.name == l.name)
+                    && !kept.iter().any(|t| t.machine.name == l.name)
+                {
+                    ops.push(Op::DelMachine(l.clone()));
+                }
+            }
+        }
+
+        Plan { ops }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Perform the operations in order, stopping at the first failure
+    pub fn apply(self, oqa: &OpenQA) -> impl Future<Item=(), Error=Error> + Send {
+        let oqa = oqa.clone();
+
+        stream::iter_ok(self.ops).for_each(move |op| {
+            info!("Applying {}", op);
+            op.apply(&oqa)
+        })
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ops.is_empty() {
+            return write!(f, "No changes");
+        }
+        for op in &self.ops {
+            writeln!(f, "{}", op)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIVE: &str = r#"{
+        "Machines": [
+            {"id": 1, "name": "64bit", "backend": "qemu",
+             "settings": [{"key": "QEMURAM", "value": "1024"}]},
+            {"id": 2, "name": "old", "backend": "qemu"}
+        ],
+        "Products": [
+            {"id": 3, "distri": "sle", "version": "15", "flavor": "DVD", "arch": "x86_64"}
+        ],
+        "TestSuites": [
+            {"id": 4, "name": "ltp", "description": "LTP"}
+        ],
+        "JobTemplates": [
+            {"id": 5, "group_name": "Kernel", "prio": 50,
+             "machine": {"id": 1, "name": "64bit"},
+             "product": {"id": 3, "distri": "sle", "version": "15", "flavor": "DVD",
+                         "arch": "x86_64"},
+             "test_suite": {"id": 4, "name": "ltp"}}
+        ]
+    }"#;
+
+    const DESIRED: &str = r#"
+machines:
+  - name: 64bit
+    backend: qemu
+    settings:
+      - key: QEMURAM
+        value: "2048"
+products:
+  - {distri: sle, version: "15", flavor: DVD, arch: x86_64}
+test_suites:
+  - {name: ltp, description: LTP}
+  - {name: kselftests}
+job_templates:
+  - group_name: Kernel
+    prio: 40
+    machine: {name: 64bit}
+    product: {distri: sle, version: "15", flavor: DVD, arch: x86_64}
+    test_suite: {name: ltp}
+  - group_name: Kernel
+    machine: {name: 64bit}
+    product: {distri: sle, version: "15", flavor: DVD, arch: x86_64}
+    test_suite: {name: kselftests}
+"#;
+
+    #[test]
+    fn plan() {
+        let live = State::from_json(LIVE).unwrap();
+        let desired = State::from_yaml(DESIRED).unwrap();
+
+        let plan = Plan::new(&desired, &live, false);
+        assert_eq!("~ machine 64bit\n    -QEMURAM=1024\n    +QEMURAM=2048\n\
+                    + test suite kselftests\n\
+                    ~ job template Kernel: sle-15-DVD-x86_64-ltp@64bit\n    prio: 50 -> 40\n\
+                    + job template Kernel: sle-15-DVD-x86_64-kselftests@64bit\n",
+                   plan.to_string());
+        match &plan.ops[0] {
+            Op::UpdMachine(m, _) => assert_eq!(1, m.id),
+            op => panic!("Unexpected {}", op),
+        }
+
+        let plan = Plan::new(&desired, &live, true);
+        assert_eq!("- machine old", plan.ops.last().unwrap().to_string());
+
+        // A template in a group `desired` doesn't manage still uses it
+        let mut live = live;
+        let mut other = live.job_templates[0].clone();
+        other.group_name = "Other".to_string();
+        other.machine = live.machines[1].clone();
+        live.job_templates.push(other);
+        assert_eq!(Plan::new(&desired, &live, false).to_string(),
+                   Plan::new(&desired, &live, true).to_string());
+
+        assert!(Plan::new(&live, &live, true).is_empty());
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn template_prio_in_place() {
+        use mock::MockServer;
+
+        let mut server = MockServer::start().unwrap();
+        let oqa = server.client();
+        let mut desired = State::from_yaml(r#"
+job_groups:
+  - name: Kernel
+machines:
+  - {name: 64bit, backend: qemu}
+products:
+  - {distri: sle, version: "15", flavor: DVD, arch: x86_64}
+test_suites:
+  - {name: ltp}
+job_templates:
+  - group_name: Kernel
+    prio: 40
+    machine: {name: 64bit}
+    product: {distri: sle, version: "15", flavor: DVD, arch: x86_64}
+    test_suite: {name: ltp}
+"#).unwrap();
+
+        let live = server.block_on(State::fetch(&oqa)).unwrap();
+        server.block_on(Plan::new(&desired, &live, false).apply(&oqa)).unwrap();
+        let id = server.block_on(State::fetch(&oqa)).unwrap().job_templates[0].id;
+
+        desired.job_templates[0].prio = 30;
+        let live = server.block_on(State::fetch(&oqa)).unwrap();
+        let plan = Plan::new(&desired, &live, false);
+        assert_eq!("~ job template Kernel: sle-15-DVD-x86_64-ltp@64bit\n    prio: 40 -> 30\n",
+                   plan.to_string());
+        server.block_on(plan.apply(&oqa)).unwrap();
+        assert_eq!(Some("1"), server.received().pop().unwrap().param("prio_only"));
+
+        let live = server.block_on(State::fetch(&oqa)).unwrap();
+        assert_eq!((id, 30), (live.job_templates[0].id, live.job_templates[0].prio));
+        assert!(Plan::new(&desired, &live, false).is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+//! How `UserAgent` sends requests
+//!
+//! `UserAgent` builds and signs requests, then hands them to a `Transport`.
+//! The default uses hyper with TLS, but anything which can turn a request
+//! into a response will do, e.g. a test double or a wrapper which logs or
+//! records what passes through it.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use http;
+use futures::future::{self, Either};
+use hyper::{Body, Chunk, Client, StatusCode};
+use hyper::client::HttpConnector;
+use hyper::rt::{Future, Stream};
+use hyper::upgrade::Upgraded;
+use hyper_tls::HttpsConnector;
+use failure::Error;
+
+/// The status, headers and complete body of a response
+pub type Response = (http::response::Parts, Chunk);
+
+pub type ResponseFuture = Box<dyn Future<Item=Response, Error=Error> + Send>;
+
+pub type UpgradeFuture =
+    Box<dyn Future<Item=(http::response::Parts, Upgraded), Error=Error> + Send>;
+
+/// Sends a request and reads the whole response
+pub trait Transport: Send + Sync {
+    fn send(&self, req: http::Request<Bytes>) -> ResponseFuture;
+
+    /// Send a request to switch protocols and return the upgraded connection
+    ///
+    /// Only transports with a real connection can do this; the default fails.
+    fn upgrade(&self, req: http::Request<Bytes>) -> UpgradeFuture {
+        Box::new(future::err(format_err!("Can't upgrade {} with this transport", req.uri())))
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Arc<T> {
+    fn send(&self, req: http::Request<Bytes>) -> ResponseFuture {
+        (**self).send(req)
+    }
+
+    fn upgrade(&self, req: http::Request<Bytes>) -> UpgradeFuture {
+        (**self).upgrade(req)
+    }
+}
+
+/// The default transport, a hyper client which supports HTTP and HTTPS
+#[derive(Clone)]
+pub struct Hyper {
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl Hyper {
+    pub fn new() -> Hyper {
+        let https = HttpsConnector::new(1).unwrap();
+        Hyper { client: Client::builder().build::<_, Body>(https) }
+    }
+}
+
+impl Default for Hyper {
+    fn default() -> Hyper {
+        Hyper::new()
+    }
+}
+
+impl Transport for Hyper {
+    fn send(&self, req: http::Request<Bytes>) -> ResponseFuture {
+        let method = req.method().clone();
+
+        Box::new(self.client.request(req.map(Body::from)).and_then(|res| {
+            let (parts, body) = res.into_parts();
+            body.concat2().map(move |body| (parts, body))
+        }).map_err(move |e| format_err!("Sending {}: {}", method, e)))
+    }
+
+    fn upgrade(&self, req: http::Request<Bytes>) -> UpgradeFuture {
+        let method = req.method().clone();
+
+        Box::new(self.client.request(req.map(Body::from))
+            .map_err(move |e| format_err!("Sending {}: {}", method, e))
+            .and_then(|res| {
+                let (parts, body) = res.into_parts();
+
+                if parts.status != StatusCode::SWITCHING_PROTOCOLS {
+                    return Either::A(body.concat2().map_err(Error::from).and_then(move |body| {
+                        Err(format_err!("Upgrade refused with {}: {}",
+                                        parts.status, String::from_utf8_lossy(&body)))
+                    }));
+                }
+                Either::B(body.on_upgrade()
+                          .map(move |io| (parts, io))
+                          .map_err(|e| format_err!("Upgrading: {}", e)))
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use {RateLimit, UserAgent};
+
+    /// Replies 429 to the first request and 200 to the rest
+    #[derive(Default)]
+    struct Fake {
+        sent: Mutex<Vec<http::Request<Bytes>>>,
+    }
+
+    impl Transport for Fake {
+        fn send(&self, req: http::Request<Bytes>) -> ResponseFuture {
+            let mut sent = self.sent.lock().unwrap();
+            let mut res = http::Response::builder();
+
+            if sent.is_empty() {
+                res.status(429).header("Retry-After", "0");
+            }
+            sent.push(req);
+            let (parts, _) = res.body(()).unwrap().into_parts();
+            Box::new(future::ok((parts, Chunk::from("{}"))))
+        }
+    }
+
+    #[test]
+    fn fake_transport() {
+        let fake = Arc::new(Fake::default());
+        let mut ua = UserAgent::new("http://fake", "key", "secret");
+        ua.set_transport(fake.clone());
+        ua.set_rate_limit(RateLimit::new(100.0, 10));
+
+        let uri = ua.url_query("machines", [("name", "64bit", false)]);
+        let (parts, body) = ua.request(http::Method::POST, uri, Some(Bytes::from("{}")))
+            .wait().unwrap();
+        assert_eq!(200, parts.status.as_u16());
+        assert_eq!(&b"{}"[..], &body[..]);
+
+        let sent = fake.sent.lock().unwrap();
+        assert_eq!(2, sent.len());
+        let req = &sent[1];
+        assert_eq!("/api/v1/machines?name=64bit", req.uri().path_and_query().unwrap().as_str());
+        assert_eq!("key", req.headers()["X-API-Key"]);
+        assert!(req.headers().contains_key("X-API-Hash"));
+        assert_eq!("application/json", req.headers()["Content-Type"]);
+        assert_eq!(&b"{}"[..], &req.body()[..]);
+    }
+
+    #[test]
+    fn retry_without_limit() {
+        let fake = Arc::new(Fake::default());
+        let mut ua = UserAgent::new("http://fake", "key", "secret");
+        ua.set_transport(fake.clone());
+
+        let (parts, _) = ua.request(http::Method::GET, ua.url("machines"), None).wait().unwrap();
+        assert_eq!(200, parts.status.as_u16());
+        assert_eq!(2, fake.sent.lock().unwrap().len());
+    }
+
+    #[test]
+    fn upgrade_uses_transport() {
+        let mut ua = UserAgent::new("http://fake", "key", "secret");
+        ua.set_transport(Fake::default());
+
+        let err = ua.upgrade(ua.url("ws"), http::HeaderMap::new()).wait().err().unwrap();
+        assert!(err.to_string().starts_with("Can't upgrade"), "{}", err);
+    }
+}
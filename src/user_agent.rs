@@ -2,31 +2,106 @@ use crypto::hmac::Hmac;
 use crypto::sha1::Sha1;
 use crypto::mac::Mac;
 use time::get_time;
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use http::{self, uri::Uri};
-use http::header::HeaderValue;
-use hyper::{Client, Body, Chunk};
-use hyper::client::HttpConnector;
-use hyper::rt::{Future, Stream};
-use hyper_tls::HttpsConnector;
+use http::header::{HeaderMap, HeaderValue};
+use hyper::Chunk;
+use hyper::upgrade::Upgraded;
+use hyper::rt::Future;
+use futures::future::{self, Loop};
 use failure::Error;
 
-type MyClient = Client<HttpsConnector<HttpConnector>>;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-pub struct UserAgent {
-    client: MyClient,
-    base_uri: BytesMut,
+use tracing::{self, field};
+use tracing_futures::Instrument;
+
+use fixture::{Fixtures, Recorder};
+use middleware::{redacted, Attempt, Middleware};
+use rate::{self, Limiter, RateLimit};
+use transport::{Hyper, Transport};
+
+struct Credentials {
     key: String,
     secret: String,
 }
 
+#[derive(Clone)]
+pub struct UserAgent {
+    /// `inner` wrapped in a recorder when there are fixtures
+    transport: Arc<dyn Transport>,
+    inner: Arc<dyn Transport>,
+    fixtures: Option<Arc<Fixtures>>,
+    base_uri: BytesMut,
+    creds: Arc<Credentials>,
+    limiter: Option<Arc<Limiter>>,
+    middleware: Arc<Vec<Arc<dyn Middleware>>>,
+}
+
 const HOST: &str = "http://localhost";
 const KEY: &str = "1234567890ABCDEF";
 const SECRET: &str = "1234567890ABCDEF";
 
+const API_PATH: &str = "/api/v1/";
+
+/// How many times to retry a request which got 429 Too Many Requests
+const MAX_RETRIES: u32 = 5;
+
 const XMAP_U: &[u8] = b"0123456789ABCDEF";
 const XMAP_L: &[u8] = b"0123456789abcdef";
 
+fn millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + u64::from(d.subsec_millis())
+}
+
+/// The `X-API-Hash` of a request to `url` made at `time`
+///
+/// This is a hex encoded HMAC-SHA1 of the path, query and time.
+pub fn api_hash(secret: &str, url: &Uri, time: &str) -> String {
+    let mut mac = Hmac::new(Sha1::new(), secret.as_bytes());
+
+    mac.input(url.path().as_bytes());
+    if let Some(q) = url.query() {
+        mac.input(b"?");
+        mac.input(q.as_bytes());
+    }
+    mac.input(time.as_bytes());
+
+    String::from_utf8(hex_str(mac.result().code()).to_vec()).unwrap()
+}
+
+impl Credentials {
+    fn hash(&self, url: &Uri, time: &str) -> HeaderValue {
+        HeaderValue::from_str(&api_hash(&self.secret, url, time)).unwrap()
+    }
+
+    fn request(&self, method: http::Method, url: Uri, body: Option<Bytes>, sign: bool)
+               -> http::Request<Bytes>
+    {
+        let mut req = match body {
+            Some(body) => {
+                let mut req = http::Request::new(body);
+                req.headers_mut().insert("Content-Type",
+                                         HeaderValue::from_static("application/json"));
+                req
+            },
+            None => http::Request::new(Bytes::new()),
+        };
+        *req.method_mut() = method;
+        if sign {
+            let hdrs = req.headers_mut();
+            hdrs.insert("Accept", HeaderValue::from_str("application/json").unwrap());
+            let t = format!("{}", get_time().sec);
+            hdrs.insert("X-API-Microtime", HeaderValue::from_str(&t).unwrap());
+            hdrs.insert("X-API-Key", HeaderValue::from_str(&self.key).unwrap());
+            hdrs.insert("X-API-Hash", self.hash(&url, &t));
+        }
+        *req.uri_mut() = url;
+        req
+    }
+}
+
 impl UserAgent {
     pub fn new<U, S, T>(host: U, key: S, secret: T) -> UserAgent
     where
@@ -34,56 +109,204 @@ impl UserAgent {
         S: Into<String>,
         T: Into<String>,
     {
-        let https = HttpsConnector::new(1).unwrap();
-        let client = Client::builder().build::<_, Body>(https);
         let mut base_uri = BytesMut::from(host);
-        base_uri.extend_from_slice(&b"/api/v1/"[..]);
+        base_uri.extend_from_slice(API_PATH.as_bytes());
 
+        let inner: Arc<dyn Transport> = Arc::new(Hyper::new());
         UserAgent {
-            client,
+            transport: inner.clone(),
+            inner,
+            fixtures: None,
             base_uri,
-            key: key.into(),
-            secret: secret.into(),
+            creds: Arc::new(Credentials {
+                key: key.into(),
+                secret: secret.into(),
+            }),
+            limiter: None,
+            middleware: Arc::default(),
         }
-        
     }
 
-    fn hash(&self, url: &Uri, time: &str) -> HeaderValue {
-        let mut mac = Hmac::new(Sha1::new(), self.secret.as_bytes());
+    /// Send requests with `transport` instead of the default hyper client
+    ///
+    /// Any fixtures still record or replay in front of it.
+    pub fn set_transport<T: Transport + 'static>(&mut self, transport: T) {
+        self.inner = Arc::new(transport);
+        self.wrap();
+    }
+
+    /// Limit the rate of requests and hold them all back while the server
+    /// asks for a pause with 429
+    pub fn set_rate_limit(&mut self, limit: RateLimit) {
+        self.limiter = Some(Arc::new(Limiter::new(limit)));
+    }
+
+    /// Run `middleware` around each request, after any already added
+    pub fn add_middleware<M: Middleware + 'static>(&mut self, middleware: M) {
+        let mut all = self.middleware.as_ref().clone();
+        all.push(Arc::new(middleware));
+        self.middleware = Arc::new(all);
+    }
+
+    /// Record responses to, or replay them from, a fixture file
+    ///
+    /// This applies to the transport whether it is set before or after.
+    /// Replaying doesn't touch the network, so the rate limit is ignored
+    /// while replaying.
+    pub fn set_fixtures(&mut self, fixtures: Fixtures) {
+        self.fixtures = Some(Arc::new(fixtures));
+        self.wrap();
+    }
 
-        mac.input(url.path().as_bytes());
-        if let Some(q) = url.query() {
-            mac.input(b"?");
-            mac.input(q.as_bytes());
+    /// The rate limiter, unless replaying makes it pointless
+    fn limiter(&self) -> Option<Arc<Limiter>> {
+        match self.fixtures {
+            Some(ref f) if f.is_replaying() => None,
+            _ => self.limiter.clone(),
         }
-        mac.input(time.as_bytes());
+    }
 
-        HeaderValue::from_shared(hex_str(mac.result().code()).into()).unwrap()
+    fn wrap(&mut self) {
+        self.transport = match self.fixtures {
+            Some(ref f) => Arc::new(Recorder::new(self.inner.clone(), f.clone())),
+            None => self.inner.clone(),
+        };
+    }
+
+    /// Send a request, waiting for the rate limiter and retrying on 429
+    ///
+    /// A retry waits for the server's `Retry-After` delay, or backs off
+    /// exponentially without one.
+    fn send(&self, method: http::Method, url: Uri, body: Option<Bytes>, sign: bool)
+            -> impl Future<Item=(http::response::Parts, Chunk), Error=Error>
+    {
+        let transport = self.transport.clone();
+        let creds = self.creds.clone();
+        let limiter = self.limiter();
+        let middleware = self.middleware.clone();
+        let span = tracing::info_span!("openqa_request", method = %method, path = url.path(),
+                                       status = field::Empty, latency_ms = field::Empty,
+                                       retries = field::Empty);
+        let started = Instant::now();
+        let done = span.clone();
+
+        future::loop_fn((0, Duration::default()), move |(retries, backoff)| {
+            let wait = limiter.as_ref().map(|l| l.acquire()).unwrap_or_default().max(backoff);
+            let (transport, creds) = (transport.clone(), creds.clone());
+            let (method, url, body) = (method.clone(), url.clone(), body.clone());
+            let (limiter, middleware) = (limiter.clone(), middleware.clone());
+
+            if wait > Duration::default() {
+                tracing::debug!(retries, wait_ms = millis(wait), "Waiting to send");
+            }
+            rate::delay(wait).and_then(move |_| {
+                // Signatures contain the time, so sign each attempt when it is sent
+                let mut req = creds.request(method.clone(), url.clone(), body, sign);
+                for m in middleware.iter() {
+                    m.before_send(&mut req);
+                }
+
+                tracing::debug!(retries, headers = ?redacted(req.headers()), "Sending");
+                let sent = Instant::now();
+                transport.send(req).then(move |res| {
+                    let attempt = Attempt {
+                        method: &method,
+                        uri: &url,
+                        retries,
+                        latency: sent.elapsed(),
+                    };
+                    for m in middleware.iter() {
+                        m.after_response(&attempt, res.as_ref());
+                    }
+                    res
+                })
+            }).map(move |(parts, body)| {
+                if parts.status != http::StatusCode::TOO_MANY_REQUESTS || retries >= MAX_RETRIES {
+                    return Loop::Break((parts, body, retries));
+                }
+                let wait = rate::retry_after(&parts.headers)
+                    .unwrap_or_else(|| Duration::from_secs(1 << retries));
+
+                tracing::warn!(retries, wait_ms = millis(wait), "Too many requests, retrying");
+                // Hold back the client's other requests as well
+                if let Some(ref l) = limiter {
+                    l.pause(wait);
+                }
+                Loop::Continue((retries + 1, wait))
+            })
+        }).then(move |res| {
+            done.record("latency_ms", millis(started.elapsed()));
+            match res {
+                Ok((parts, body, retries)) => {
+                    done.record("status", parts.status.as_u16());
+                    done.record("retries", retries);
+                    Ok((parts, body))
+                },
+                Err(e) => {
+                    tracing::warn!(error = %e, "Request failed");
+                    Err(e)
+                },
+            }
+        }).instrument(span)
+    }
+
+    fn signed(&self, method: http::Method, url: Uri) -> impl Future<Item=Chunk, Error=Error> {
+        self.send(method, url, None, true).map(|(_, body)| body)
     }
 
     pub fn post(&self, url: Uri) -> impl Future<Item=Chunk, Error=Error> {
-        let mut req = http::Request::new(Body::default());
-        *req.method_mut() = http::Method::POST;
-        {
-            let hdrs = req.headers_mut();
-            hdrs.insert("Accept", HeaderValue::from_str("application/json").unwrap());
-            let t = format!("{}", get_time().sec);
-            hdrs.insert("X-API-Microtime", HeaderValue::from_str(&t).unwrap());
-            hdrs.insert("X-API-Key", HeaderValue::from_str(&self.key).unwrap());
-            hdrs.insert("X-API-Hash", self.hash(&url, &t));
-        }
-        *req.uri_mut() = url;
-        debug!("POST {:#?}", req);
+        self.signed(http::Method::POST, url)
+    }
 
-        self.client.request(req).and_then(|res| {
-            res.into_body().concat2()
-        }).map_err(|e| format_err!("Posting: {}", e))
+    pub fn put(&self, url: Uri) -> impl Future<Item=Chunk, Error=Error> {
+        self.signed(http::Method::PUT, url)
+    }
+
+    pub fn delete(&self, url: Uri) -> impl Future<Item=Chunk, Error=Error> {
+        self.signed(http::Method::DELETE, url)
     }
 
     pub fn get(&self, url: Uri) -> impl Future<Item=Chunk, Error=Error> {
-        self.client.get(url).and_then(|res| {
-            res.into_body().concat2()
-        }).map_err(|e| Error::from(e))
+        self.get_response(url).map(|(_, body)| body)
+    }
+
+    /// Like `get`, but also return the status and headers
+    pub fn get_response(&self, url: Uri)
+                        -> impl Future<Item=(http::response::Parts, Chunk), Error=Error>
+    {
+        self.send(http::Method::GET, url, None, false)
+    }
+
+    /// Send a signed request with any method and an optional JSON body
+    pub fn request(&self, method: http::Method, url: Uri, body: Option<Bytes>)
+                   -> impl Future<Item=(http::response::Parts, Chunk), Error=Error>
+    {
+        self.send(method, url, body, true)
+    }
+
+    /// Send a signed GET asking to switch protocols, with extra headers
+    ///
+    /// This waits for the rate limit and runs the middleware's `before_send`
+    /// like any other request, then upgrades through the transport. It isn't
+    /// retried, and fixtures neither record nor replay it.
+    pub fn upgrade(&self, url: Uri, headers: HeaderMap)
+                   -> impl Future<Item=(http::response::Parts, Upgraded), Error=Error>
+    {
+        let transport = self.transport.clone();
+        let creds = self.creds.clone();
+        let middleware = self.middleware.clone();
+        let wait = self.limiter().map(|l| l.acquire()).unwrap_or_default();
+
+        rate::delay(wait).and_then(move |_| {
+            let mut req = creds.request(http::Method::GET, url, None, true);
+            for (k, v) in headers.iter() {
+                req.headers_mut().insert(k.clone(), v.clone());
+            }
+            for m in middleware.iter() {
+                m.before_send(&mut req);
+            }
+            transport.upgrade(req)
+        })
     }
 
     fn url_bytes(&self, path: &str) -> BytesMut {
@@ -96,6 +319,16 @@ impl UserAgent {
         Uri::from_shared(self.url_bytes(path).into()).unwrap()
     }
 
+    /// A URL outside of the API, e.g. `tests/1`
+    pub fn host_url(&self, path: &str) -> Uri {
+        let mut bytes = self.base_uri.clone();
+        let len = bytes.len() - API_PATH.len();
+
+        bytes.truncate(len + 1);
+        bytes.extend_from_slice(path.as_bytes());
+        Uri::from_shared(bytes.into()).unwrap()
+    }
+
     pub fn url_query<K, V, P>(&self, path: &str, pairs: P) -> Uri
     where
         K: AsRef<[u8]>,
@@ -134,7 +367,7 @@ fn percent_encode(data: &[u8], out: &mut BytesMut) {
     out.reserve(data.len() * 3);
     for b in data {
         match *b {
-            b'0' ... b'9' | b'A' ... b'Z' | b'a' ... b'z' | b'-' | b'_' | b'.' | b'~' => {
+            b'0' ..= b'9' | b'A' ..= b'Z' | b'a' ..= b'z' | b'-' | b'_' | b'.' | b'~' => {
                 out.put(*b);
             },
             b' ' => out.put(b'+'),
@@ -169,7 +402,7 @@ mod tests {
         mac.input(payload.as_bytes());
         let res = mac.result();
         let raw = res.code();
-        let hex = hex_str(&raw);
+        let hex = hex_str(raw);
         assert_eq!(raw.len() * 2, hex.len());
         assert_eq!("f4d2e8996c1d68aff0892b248a92651c8d3e9a4c", &hex);
     }
@@ -0,0 +1,295 @@
+//! Poll jobs until they finish
+//!
+//! The jobs are fetched together, first straight away and then at growing
+//! intervals. A job which is restarted gets a `clone_id` and the clone is
+//! waited for instead, so the results are those of the last clone.
+
+use std::cmp;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either, Loop};
+use futures::Future;
+use failure::Error;
+
+use rate;
+use {Job, OpenQA};
+
+type ProgressFn = Box<dyn FnMut(&Progress) + Send>;
+
+/// How often to poll, for how long and who to tell
+pub struct WaitOptions {
+    /// Time between the first polls
+    pub interval: Duration,
+    /// The interval is multiplied by this after each poll
+    pub backoff: f64,
+    pub max_interval: Duration,
+    /// Give up after this long
+    pub timeout: Option<Duration>,
+    /// Wait for the clone of a restarted job instead of stopping
+    pub follow_clones: bool,
+    on_progress: Option<ProgressFn>,
+}
+
+impl Default for WaitOptions {
+    fn default() -> WaitOptions {
+        WaitOptions {
+            interval: Duration::from_secs(5),
+            backoff: 1.5,
+            max_interval: Duration::from_secs(60),
+            timeout: None,
+            follow_clones: true,
+            on_progress: None,
+        }
+    }
+}
+
+impl WaitOptions {
+    pub fn with_interval(mut self, interval: Duration, max_interval: Duration) -> WaitOptions {
+        self.interval = interval;
+        self.max_interval = max_interval;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> WaitOptions {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Call `f` after each poll
+    pub fn on_progress<F: FnMut(&Progress) + Send + 'static>(mut self, f: F) -> WaitOptions {
+        self.on_progress = Some(Box::new(f));
+        self
+    }
+}
+
+/// The state of the jobs after a poll
+pub struct Progress<'a> {
+    /// The latest clone of each job, in the order they were asked for
+    pub jobs: &'a [Job],
+    /// How many of them have finished
+    pub finished: usize,
+    pub elapsed: Duration,
+}
+
+/// Whether a job is done or cancelled
+pub fn is_finished(job: &Job) -> bool {
+    job.state == "done" || job.state == "cancelled"
+}
+
+struct State {
+    opts: WaitOptions,
+    /// The ID of the latest clone of each job
+    ids: Vec<i32>,
+    jobs: Vec<Option<Job>>,
+    wait: Duration,
+    interval: Duration,
+}
+
+impl State {
+    fn is_finished(&self, i: usize) -> bool {
+        match self.jobs[i] {
+            Some(ref j) => is_finished(j) && !(self.opts.follow_clones && j.clone_id.is_some()),
+            None => false,
+        }
+    }
+
+    /// Store the polled jobs, returning whether any were cloned
+    fn update(&mut self, polled: Vec<Job>) -> Result<bool, Error> {
+        let mut cloned = false;
+
+        let pending: Vec<usize> = (0..self.ids.len()).filter(|i| !self.is_finished(*i)).collect();
+
+        for i in pending {
+            let job = match polled.iter().find(|j| j.id == self.ids[i]) {
+                Some(j) => j.clone(),
+                None => bail!("Job {} not found", self.ids[i]),
+            };
+            if let (true, Some(clone)) = (self.opts.follow_clones, job.clone_id) {
+                debug!("Job {} was restarted as {}", job.id, clone);
+                self.ids[i] = clone;
+                cloned = true;
+            }
+            self.jobs[i] = Some(job);
+        }
+        Ok(cloned)
+    }
+}
+
+/// Poll `ids` until they are all finished and return the final jobs
+pub fn wait_for_jobs(oqa: OpenQA, ids: &[i32], opts: WaitOptions)
+                     -> impl Future<Item=Vec<Job>, Error=Error> + Send
+{
+    // Asking for no ids would fetch every job
+    if ids.is_empty() {
+        return Either::A(future::ok(Vec::new()));
+    }
+
+    let start = Instant::now();
+    let state = State {
+        ids: ids.to_vec(),
+        jobs: ids.iter().map(|_| None).collect(),
+        wait: Duration::default(),
+        interval: opts.interval,
+        opts,
+    };
+
+    Either::B(future::loop_fn(state, move |mut state| {
+        let ids: Vec<i32> = (0..state.ids.len())
+            .filter(|i| !state.is_finished(*i))
+            .map(|i| state.ids[i])
+            .collect();
+        let oqa = oqa.clone();
+
+        let timeout = state.opts.timeout;
+        rate::delay(state.wait).and_then(move |_| {
+            let poll = oqa.get_jobs(&ids);
+            let timeout = match timeout {
+                Some(t) => t,
+                None => return Either::A(poll),
+            };
+            // Don't let a slow request outlast the timeout
+            let left = timeout.checked_sub(start.elapsed()).unwrap_or_default();
+            Either::B(poll.select2(rate::delay(left)).then(move |res| match res {
+                Ok(Either::A((polled, _))) => Ok(polled),
+                Ok(Either::B(_)) => Err(timed_out(timeout, &ids)),
+                Err(Either::A((e, _))) | Err(Either::B((e, _))) => Err(e),
+            }))
+        }).and_then(move |polled| {
+            let cloned = state.update(polled)?;
+            let finished = (0..state.ids.len()).filter(|i| state.is_finished(*i)).count();
+            let elapsed = start.elapsed();
+
+            if let Some(ref mut f) = state.opts.on_progress {
+                let jobs: Vec<Job> = state.jobs.iter().flatten().cloned().collect();
+                f(&Progress { jobs: &jobs, finished, elapsed });
+            }
+            if finished == state.ids.len() {
+                return Ok(Loop::Break(state.jobs.into_iter().flatten().collect()));
+            }
+
+            if let Some(timeout) = state.opts.timeout {
+                if elapsed >= timeout {
+                    let waiting: Vec<_> = (0..state.ids.len())
+                        .filter(|i| !state.is_finished(*i))
+                        .map(|i| state.ids[i])
+                        .collect();
+                    return Err(timed_out(timeout, &waiting));
+                }
+            }
+
+            // A clone has just been scheduled, so start polling quickly again
+            if cloned {
+                state.interval = state.opts.interval;
+            }
+            state.wait = state.interval;
+            if let Some(timeout) = state.opts.timeout {
+                state.wait = cmp::min(state.wait, timeout - elapsed);
+            }
+            state.interval = cmp::min(state.interval.mul_f64(state.opts.backoff),
+                                      state.opts.max_interval);
+            Ok(Loop::Continue(state))
+        })
+    }))
+}
+
+fn timed_out(timeout: Duration, waiting: &[i32]) -> Error {
+    let waiting: Vec<_> = waiting.iter().map(|id| id.to_string()).collect();
+    format_err!("Timed out after {:?} waiting for jobs {}", timeout, waiting.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use bytes::Bytes;
+    use http;
+    use serde_json;
+    use tokio::runtime::Runtime;
+    use transport::{ResponseFuture, Transport};
+
+    /// A job's result and clone ID
+    type Step = (&'static str, Option<i32>);
+
+    /// Answers with the next step of each job asked for
+    struct Script {
+        steps: Mutex<HashMap<i32, Vec<Step>>>,
+    }
+
+    impl Transport for Script {
+        fn send(&self, req: http::Request<Bytes>) -> ResponseFuture {
+            let mut steps = self.steps.lock().unwrap();
+            let query = req.uri().query().unwrap_or_default();
+            let jobs: Vec<String> = query.split('&').filter_map(|p| {
+                let id: i32 = p.trim_start_matches("ids=").parse().ok()?;
+                let job = steps.get_mut(&id)?;
+                let (result, clone) = if job.len() > 1 { job.remove(0) } else { job[0] };
+                serde_json::to_string(&Job::fake(id, "t").with_result(result).with_clone(clone)).ok()
+            }).collect();
+
+            let (parts, _) = http::Response::new(()).into_parts();
+            let body = format!(r#"{{"jobs": [{}]}}"#, jobs.join(","));
+            Box::new(future::ok((parts, body.into())))
+        }
+    }
+
+    fn script(steps: &[(i32, &[Step])]) -> OpenQA {
+        let steps = steps.iter().map(|(id, s)| (*id, s.to_vec())).collect();
+        OpenQA::new("http://script", "", "").with_transport(Script { steps: Mutex::new(steps) })
+    }
+
+    fn quick() -> WaitOptions {
+        WaitOptions::default()
+            .with_interval(Duration::from_millis(1), Duration::from_millis(4))
+    }
+
+    #[test]
+    fn clones_and_progress() {
+        let oqa = script(&[
+            (1, &[("none", None), ("passed", None)]),
+            (2, &[("user_restarted", Some(3))]),
+            (3, &[("none", None), ("failed", None)]),
+        ]);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let progress = seen.clone();
+        let opts = quick().on_progress(move |p| {
+            progress.lock().unwrap().push((p.finished, p.jobs.iter().map(|j| j.id).collect::<Vec<_>>()));
+        });
+
+        // The threaded runtime needs the future to be Send and 'static
+        let jobs = Runtime::new().unwrap().block_on(oqa.wait_for_jobs(&[1, 2], opts)).unwrap();
+
+        let res: Vec<_> = jobs.iter().map(|j| (j.id, j.result.as_str())).collect();
+        assert_eq!(vec![(1, "passed"), (3, "failed")], res);
+        assert_eq!(vec![(0, vec![1, 2]), (1, vec![1, 3]), (2, vec![1, 3])], *seen.lock().unwrap());
+    }
+
+    #[test]
+    fn timeout() {
+        let oqa = script(&[(1, &[("passed", None)]), (2, &[("none", None)])]);
+        let opts = quick().with_timeout(Duration::from_millis(20));
+
+        let err = Runtime::new().unwrap().block_on(wait_for_jobs(oqa, &[1, 2], opts)).unwrap_err();
+        assert_eq!("Timed out after 20ms waiting for jobs 2", err.to_string());
+    }
+
+    /// Never answers
+    struct Hang;
+
+    impl Transport for Hang {
+        fn send(&self, _: http::Request<Bytes>) -> ResponseFuture {
+            Box::new(future::empty())
+        }
+    }
+
+    #[test]
+    fn slow_poll_and_no_jobs() {
+        let oqa = OpenQA::new("http://hang", "", "").with_transport(Hang);
+        let opts = quick().with_timeout(Duration::from_millis(20));
+
+        let err = Runtime::new().unwrap().block_on(oqa.wait_for_jobs(&[1, 2], opts)).unwrap_err();
+        assert_eq!("Timed out after 20ms waiting for jobs 1, 2", err.to_string());
+
+        assert!(oqa.wait_for_jobs(&[], quick()).wait().unwrap().is_empty());
+    }
+}